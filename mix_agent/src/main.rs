@@ -0,0 +1,49 @@
+use log::{error, info};
+use mix_agent_common::mix_config::{config_path, init_logger, ConfigError};
+use mix_agent_common::mix_host::Host;
+use mix_agent_common::{get_global_config, mix_batch, report_config_error};
+use std::process;
+use std::sync::{Arc, Mutex};
+use sysinfo::{System, SystemExt};
+
+const AGENT_NAME: &str = "mix_agent";
+
+/// 可托管的探针，全局配置`agents`中可写作`cpu`或`mix_agent_cpu`
+const AGENTS: [&str; 8] = ["cpu", "memory", "disk", "machine", "process", "directory", "api", "service"];
+
+fn main() {
+    init_logger(AGENT_NAME);
+    let global_config = get_global_config();
+
+    //sysinfo的刷新开销较大，多个探针共用一个
+    let sys = Arc::new(Mutex::new(System::new_all()));
+    let mut host = Host::new();
+    let mut errors = vec![];
+    for (i, agent) in global_config.agents.iter().enumerate() {
+        let name = agent.trim().trim_start_matches("mix_agent_");
+        if host.agent_names().contains(&format!("mix_agent_{}", name).as_str()) {
+            continue;
+        }
+        match name {
+            "cpu" => mix_agent_cpu::Cpu::register(&mut host, sys.clone()),
+            "memory" => mix_agent_memory::Memory::register(&mut host, sys.clone()),
+            "disk" => mix_agent_disk::Disk::register(&mut host),
+            "machine" => mix_agent_machine::Machine::register(&mut host, sys.clone()),
+            "process" => mix_agent_process::Process::register(&mut host, sys.clone()),
+            "directory" => mix_agent_directory::Directory::register(&mut host),
+            "api" => mix_agent_api::Result::register(&mut host),
+            "service" => mix_agent_service::Service::register(&mut host),
+            _ => errors.push(ConfigError::field(&config_path("global"), &format!("agents[{}]", i), &format!("未知的探针`{}`，可选值: {}", agent, AGENTS.join(", ")))),
+        }
+    }
+    for e in errors.iter() {
+        report_config_error(e, AGENT_NAME);
+    }
+    if host.agent_names().is_empty() {
+        error!("全局配置`agents`中没有可运行的探针，程序退出");
+        mix_batch::flush();
+        process::exit(1);
+    }
+    info!("托管的探针: {:?}", host.agent_names());
+    host.run();
+}
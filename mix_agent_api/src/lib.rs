@@ -1,8 +1,10 @@
 use access_json::JSONQuery;
 use chrono::{Local, NaiveDateTime};
 use log::{error, info, warn};
-use mix_agent_common::mix_config::{init_logger, MixConfig};
-use mix_agent_common::{init_log, mix_config, post_log, GlobalConfig, LogLevel, Monitor, StripBom};
+use mix_agent_common::mix_collector::{CollectError, Collector};
+use mix_agent_common::mix_config::{init_logger, AgentConfig, MixConfig};
+use mix_agent_common::mix_host::Host;
+use mix_agent_common::{get_global_config, GlobalConfig, Monitor, StripBom};
 
 use evalexpr::*;
 use serde::{Deserialize, Serialize};
@@ -50,6 +52,8 @@ pub struct ApiAgentConfig {
     #[serde(default = "default_cron")]
     cron: String,
     #[serde(default)]
+    timezone: String,
+    #[serde(default)]
     auth: Auth,
     #[serde(default)]
     target_source: TargetSource,
@@ -62,6 +66,7 @@ impl Default for ApiAgentConfig {
         ApiAgentConfig {
             name: "".to_string(),
             cron: default_cron(),
+            timezone: String::new(),
             auth: Default::default(),
             target_source: Default::default(),
             targets: vec![],
@@ -150,86 +155,129 @@ impl MixConfig for ApiAgentConfig {
         ApiAgentConfig::default()
     }
 }
+
+impl AgentConfig for ApiAgentConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+
+    fn validate(&self) -> std::result::Result<(), (String, String)> {
+        if self.target_source.ip.trim().is_empty() {
+            return Err(("target-source.ip".to_string(), "未配置目标ip".to_string()));
+        }
+        for (i, target) in self.targets.iter().enumerate() {
+            for (k, v) in target.keywords.iter() {
+                try_parse_exp(v).map_err(|e| (format!("targets[{}].keywords.{}", i, k), e))?;
+            }
+            if let Some(condition) = &target.condition {
+                for (k, v) in condition.vars.iter() {
+                    JSONQuery::parse(v).map_err(|e| (format!("targets[{}].condition.vars.{}", i, k), format!("jsonPath无效 {:?}", e)))?;
+                }
+                build_operator_tree(&condition.when).map_err(|e| (format!("targets[{}].condition.when", i), e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+}
 impl Monitor for Result {
     fn collect(&self) {
-        let global_config = mix_config::load::<GlobalConfig>("global");
-        let agent_config = mix_config::load::<ApiAgentConfig>(AGENT_NAME);
+        let mut host = Host::new();
+        Result::register(&mut host);
+        host.run();
+    }
+}
 
-        let mut tags = vec![];
-        tags.push("agent-desc|api监控".to_owned());
+impl Result {
+    /// 注册到探针宿主
+    pub fn register(host: &mut Host) {
+        //`target-source.ip`等由ApiAgentConfig::validate校验，配置无效时等待配置修正
+        host.add_collector(ApiCollector);
+    }
+}
 
-        if agent_config.target_source.ip.trim().is_empty() {
-            error!("{}", "target_source配置不正确");
-            let log = init_log("agent", "未配置目标ip`target-source.ip`", LogLevel::Error, Box::new(tags.clone()), "", AGENT_NAME);
-            post_log(&log);
-            return;
-        }
+pub struct ApiCollector;
 
-        info!("{:?}", global_config);
-        info!("{:?}", agent_config);
-
-        Self::begin(&agent_config.cron, || {
-            let mut result = Result::default();
-            result.name = agent_config.name.clone();
-            result.auth = agent_config.auth.enabled;
-
-            let auth_enabled = agent_config.auth.enabled;
-            let token_url = &agent_config.auth.token.url;
-            let token_json_path = &agent_config.auth.token.json_path;
-
-            if auth_enabled {
-                let client = reqwest::blocking::Client::new();
-                let res = client.post(token_url).timeout(core::time::Duration::from_millis(global_config.timeout as u64)).json(&agent_config.auth.keys).send();
-                match res {
-                    Ok(res) => {
-                        if res.status().is_success() {
-                            let success = res.status().clone();
-                            let status = res.status().as_u16();
-                            let text = res.text().unwrap();
-                            let token = get_token(text.as_str(), token_json_path);
-
-                            let trs = check_targets(&global_config, &agent_config, token);
-                            result.success = success.is_success();
-                            result.status = status;
-                            result.message = text.clone();
-                            result.targets = trs;
-                        } else {
-                            let status = res.status().as_u16();
-                            let text = res.text().unwrap();
-                            error!("{} {}", agent_config.name, text);
-                            result.status = status;
-                            result.message = text.clone();
-                        }
-                    }
-                    Err(e) => {
-                        error!("{} {}", agent_config.name, e.to_string());
-                        let msg = format!("{}:{}", agent_config.name, e.to_string());
-                        let log = init_log("agent", msg.as_str(), LogLevel::Error, Box::new(tags.clone()), &result, AGENT_NAME);
-                        post_log(&log);
+impl Collector for ApiCollector {
+    type Config = ApiAgentConfig;
+    type Output = Result;
+
+    fn agent_name(&self) -> &str {
+        AGENT_NAME
+    }
+
+    fn category(&self) -> &str {
+        "api"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|api监控".to_owned()]
+    }
+
+    fn sample(&mut self, agent_config: &ApiAgentConfig) -> std::result::Result<Result, CollectError> {
+        let global_config = get_global_config();
+        let mut result = Result::default();
+        result.name = agent_config.name.clone();
+        result.auth = agent_config.auth.enabled;
+
+        let auth_enabled = agent_config.auth.enabled;
+        let token_url = &agent_config.auth.token.url;
+        let token_json_path = &agent_config.auth.token.json_path;
+
+        if auth_enabled {
+            let client = reqwest::blocking::Client::new();
+            let res = client.post(token_url).timeout(core::time::Duration::from_millis(global_config.timeout as u64)).json(&agent_config.auth.keys).send();
+            match res {
+                Ok(res) => {
+                    if res.status().is_success() {
+                        let success = res.status().clone();
+                        let status = res.status().as_u16();
+                        let text = res.text().unwrap();
+                        let token = get_token(text.as_str(), token_json_path);
+
+                        let trs = check_targets(&global_config, agent_config, token);
+                        result.success = success.is_success();
+                        result.status = status;
+                        result.message = text.clone();
+                        result.targets = trs;
+                    } else {
+                        let status = res.status().as_u16();
+                        let text = res.text().unwrap();
+                        error!("{} {}", agent_config.name, text);
+                        result.status = status;
+                        result.message = text.clone();
                     }
                 }
-            } else {
-                let trs = check_targets(&global_config, &agent_config, String::new());
-                result.success = true;
-                result.status = 200;
-                result.message = String::new();
-                result.targets = trs;
+                Err(e) => {
+                    error!("{} {}", agent_config.name, e.to_string());
+                    let msg = format!("{}:{}", agent_config.name, e.to_string());
+                    return Err(CollectError::error(&msg).with_data(&result));
+                }
             }
+        } else {
+            let trs = check_targets(&global_config, agent_config, String::new());
+            result.success = true;
+            result.status = 200;
+            result.message = String::new();
+            result.targets = trs;
+        }
 
-            let log = init_log("api", "", LogLevel::Info, Box::new(tags.clone()), &result, AGENT_NAME);
-            post_log(&log);
-        });
+        Ok(result)
     }
 }
 
-fn check_rule(token: &str, target: &Target) -> (Vec<MatchResult>, String, bool, u16) {
+fn check_rule(token: &str, target: &Target, global_config: &GlobalConfig) -> (Vec<MatchResult>, String, bool, u16) {
     let client = reqwest::blocking::Client::new();
     let url = target.url.as_str();
+    let timeout = Duration::from_millis(global_config.timeout);
     let res = if target.auth {
         info!("token: {}", token);
-        client.get(url).bearer_auth(token).send()
+        client.get(url).timeout(timeout).bearer_auth(token).send()
     } else {
-        client.get(url).send()
+        client.get(url).timeout(timeout).send()
     };
 
     let mut mrs: Vec<MatchResult> = vec![];
@@ -318,15 +366,24 @@ fn check_rule(token: &str, target: &Target) -> (Vec<MatchResult>, String, bool,
 }
 
 fn parse_exp(exp: String) -> Exp {
+    match try_parse_exp(&exp) {
+        Ok(exp) => exp,
+        Err(msg) => {
+            warn!("{}", msg);
+            panic!("{}", msg);
+        }
+    }
+}
+
+///解析关键字表达式，同时校验jsonPath及时间阀值，用于配置校验
+fn try_parse_exp(exp: &str) -> std::result::Result<Exp, String> {
     //N|.data.defaultFunction[0].sort|6.5
     let items: Vec<&str> = exp.split("|").collect();
     if items.len() < 3 {
-        let msg = format!("表达式配置错: {}，正确格式为: T(类型标识)|jsonPath|V(匹配的值)", exp);
-        warn!("{}", msg);
-        panic!("{}", msg);
+        return Err(format!("表达式配置错: {}，正确格式为: T(类型标识)|jsonPath|V(匹配的值)", exp));
     }
 
-    Exp {
+    let exp = Exp {
         marker: items.get(0).value().to_string(),
         json_path: items.get(1).value().to_string(),
         value: items.get(2).value().trim().to_string(),
@@ -335,7 +392,16 @@ fn parse_exp(exp: String) -> Exp {
         } else {
             String::new()
         },
+    };
+
+    if let Err(e) = JSONQuery::parse(exp.json_path.as_str()) {
+        return Err(format!("jsonPath无效: {}, {:?}", exp.json_path, e));
+    }
+    let threshold = exp.value.trim_end_matches(['d', 'h', 'm', 's']);
+    if exp.marker == "T" && threshold.parse::<i64>().is_err() {
+        return Err(format!("时间阀值无效: {}，正确格式如: 30s、5m、1h、1d", exp.value));
     }
+    Ok(exp)
 }
 
 trait ValueGetter<T> {
@@ -364,6 +430,14 @@ fn test_get_value_none() {
     let _r = none.value();
 }
 
+#[test]
+fn test_try_parse_exp() {
+    assert!(try_parse_exp("N|.data.defaultFunction[0].sort|6.5").is_ok());
+    assert!(try_parse_exp("T|.data.time|5m|%Y-%m-%d").is_ok());
+    assert!(try_parse_exp(".data.defaultFunction[0].sort|6.5").unwrap_err().starts_with("表达式配置错"));
+    assert!(try_parse_exp("T|.data.time|five minutes").unwrap_err().starts_with("时间阀值无效"));
+}
+
 #[test]
 fn test_parse_exp() {
     parse_exp(String::from(".data.defaultFunction[0].sort|6.5"));
@@ -462,7 +536,7 @@ fn check_targets(global_config: &GlobalConfig, agent_config: &ApiAgentConfig, to
     trs
 }
 
-fn create_target_result(token: &str, trs: &mut Vec<TargetResult>, target: &Target, _global_config: &GlobalConfig) {
+fn create_target_result(token: &str, trs: &mut Vec<TargetResult>, target: &Target, global_config: &GlobalConfig) {
     let mut target_result = TargetResult {
         name: target.name.as_str().to_string(),
         success: false,
@@ -472,7 +546,7 @@ fn create_target_result(token: &str, trs: &mut Vec<TargetResult>, target: &Targe
         message: String::new(),
     };
 
-    let (mrs, msg, success, status) = check_rule(token, target);
+    let (mrs, msg, success, status) = check_rule(token, target, global_config);
     target_result.match_result = mrs;
     target_result.message = msg;
     target_result.success = success;
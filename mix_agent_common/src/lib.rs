@@ -1,16 +1,41 @@
+pub mod mix_batch;
+pub mod mix_collector;
 pub mod mix_config;
+pub mod mix_host;
+pub mod mix_influx;
+pub mod mix_metrics;
+pub mod mix_otlp;
+pub mod mix_override;
+pub mod mix_remote;
+pub mod mix_reload;
+pub mod mix_retry;
+pub mod mix_rollup;
 pub mod mix_scheduler;
-
-use crate::mix_config::MixConfig;
+pub mod mix_secret;
+pub mod mix_sign;
+pub mod mix_sink;
+pub mod mix_spool;
+pub mod mix_statsd;
+
+use crate::mix_config::{AgentConfig, ConfigError, MixConfig};
+use crate::mix_host::{Host, SchedulerConfig};
+use crate::mix_batch::BatchConfig;
+use crate::mix_metrics::PrometheusConfig;
+use crate::mix_remote::RemoteConfig;
+use crate::mix_retry::{Delivery, RetryConfig};
+use crate::mix_sign::signed_post;
+use crate::mix_sink::{default_sinks, Record, SinkConfig};
+use crate::mix_spool::{Spool, SpoolConfig};
 
 //use job_scheduler::{Job, JobScheduler};
 use log::{error, info, warn};
-use reqwest::blocking::{Client, Response};
-use reqwest::Error;
+use reqwest::blocking::Client;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json;
 
+use std::env;
+use std::path::Path;
 use std::thread;
 use std::time::Duration;
 
@@ -91,6 +116,7 @@ pub fn init_log<T: Serialize>(category: &str, content: &str, level: LogLevel, ta
         env: global_config.env,
         source: Source {
             name: agent_name.to_string(),
+            config_version: mix_remote::config_version(agent_name),
             ..Default::default()
         },
     }
@@ -110,6 +136,9 @@ pub struct Source {
     pub version: String,
     pub lang: String,
     pub ip: String,
+    ///生效的远程配置版本，未使用远程配置时为空
+    #[serde(default)]
+    pub config_version: String,
 }
 
 impl<'a> Default for Source {
@@ -121,6 +150,7 @@ impl<'a> Default for Source {
             version: "v1.0.0".to_string(),
             lang: "rust".to_string(),
             ip: source_ip,
+            config_version: "".to_string(),
         }
     }
 }
@@ -129,19 +159,23 @@ pub trait Monitor {
     fn collect(&self) {}
 
     fn begin<T: FnMut()>(cron: &String, mut action: T) {
+        mix_metrics::serve(&get_global_config().prometheus, &get_agent_name());
+
         //运行一次，不用等到cron触发
         action();
 
-        let scheduler_result = cron.parse::<Schedule>();
+        let scheduler_result = cron.parse::<Trigger>();
         match scheduler_result {
             Ok(scheduler) => {
                 let mut sched = JobScheduler::new();
-                sched.add(Job::new(scheduler, || {
+                let mut job = Job::new(scheduler, || {
                     action();
-                }));
+                });
+                get_global_config().scheduler.apply(&mut job);
+                sched.add(job);
                 loop {
                     sched.tick();
-                    thread::sleep(Duration::from_millis(500))
+                    thread::sleep(sched.time_till_next_job())
                 }
             }
             Err(e) => {
@@ -149,9 +183,19 @@ pub trait Monitor {
             }
         }
     }
+
+    /// 按探针配置中的`cron`执行，监听探针配置及全局配置，变化时重新加载
+    ///
+    /// 配置校验通过后在两次调度之间同时替换配置及调度计划，并立即执行一次；校验失败时继续使用原配置。
+    /// 启动时配置无效则等待配置修正后再开始执行。多个探针共用一个调度器见 `mix_host::Host`
+    fn begin_with_config<C: AgentConfig + Send + Sync + 'static, T: FnMut(&C) + Send + 'static>(agent_name: &str, action: T) {
+        let mut host = Host::new();
+        host.add(agent_name, action);
+        host.run();
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub struct GlobalConfig {
     pub customer_id: String,
@@ -166,6 +210,39 @@ pub struct GlobalConfig {
     pub print_log_json: bool,
     #[serde(default = "default_push_log")]
     pub push_log: bool,
+    #[serde(default)]
+    pub spool: SpoolConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<SinkConfig>,
+    #[serde(default)]
+    pub prometheus: PrometheusConfig,
+    ///配置无效时拒绝启动，默认false(记录错误后使用默认配置)
+    #[serde(default)]
+    pub strict_config: bool,
+    #[serde(default)]
+    pub remote: RemoteConfig,
+    ///`mix_agent`运行的探针，如`[cpu, memory]`，也可以是逗号分隔的字符串，便于通过环境变量`MIX_AGENTS`指定
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub agents: Vec<String>,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+}
+
+fn string_or_list<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(s) => s.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+        StringOrList::List(list) => list,
+    })
 }
 fn default_env() -> String {
     "dev".to_string()
@@ -194,7 +271,44 @@ impl Default for GlobalConfig {
             timeout: default_timeout(), //毫秒
             print_log_json: default_print_log_json(),
             push_log: default_push_log(),
+            spool: SpoolConfig::default(),
+            retry: RetryConfig::default(),
+            batch: BatchConfig::default(),
+            sinks: default_sinks(),
+            prometheus: PrometheusConfig::default(),
+            strict_config: false,
+            remote: RemoteConfig::default(),
+            agents: vec![],
+            scheduler: SchedulerConfig::default(),
+        }
+    }
+}
+
+impl GlobalConfig {
+    /// 校验全局配置，不校验`customer-id`等由服务端校验的字段
+    pub fn validate(&self, file: &str) -> Result<(), ConfigError> {
+        if !self.mix_endpoint.starts_with("http://") && !self.mix_endpoint.starts_with("https://") {
+            return Err(ConfigError::field(file, "mix-endpoint", "必须以http://或https://开头"));
+        }
+        if self.timeout == 0 {
+            return Err(ConfigError::field(file, "timeout", "必须大于0"));
+        }
+        for (i, sink) in self.sinks.iter().enumerate() {
+            sink.validate().map_err(|e| ConfigError::field(file, &format!("sinks[{}]", i), &e))?;
+        }
+        if self.remote.enabled && self.remote.interval == 0 {
+            return Err(ConfigError::field(file, "remote.interval", "必须大于0"));
         }
+        if !self.remote.path.starts_with('/') {
+            return Err(ConfigError::field(file, "remote.path", "必须以/开头"));
+        }
+        if self.scheduler.splay > mix_host::MAX_SPLAY {
+            return Err(ConfigError::field(file, "scheduler.splay", &format!("不能超过{}", mix_host::MAX_SPLAY)));
+        }
+        if !self.scheduler.timezone.trim().is_empty() {
+            mix_scheduler::parse_timezone(&self.scheduler.timezone).map_err(|e| ConfigError::field(file, "scheduler.timezone", &format!("无效的时区: {}", e)))?;
+        }
+        Ok(())
     }
 }
 
@@ -215,24 +329,63 @@ pub fn get_local_ip() -> String {
     }
 }
 
+///探针名称，即可执行文件名(不含扩展名)
+pub fn get_agent_name() -> String {
+    env::current_exe().ok().and_then(|p| p.file_stem().map(|s| s.to_string_lossy().to_string())).unwrap_or_default()
+}
+
 pub fn get_timestamp_millis() -> i64 {
     chrono::Local::now().timestamp_millis()
 }
 
+///当前使用的全局配置，首次使用时加载，热加载时替换
 pub fn get_global_config() -> GlobalConfig {
-    mix_config::load::<GlobalConfig>("global")
+    if let Ok(cache) = GLOBAL_CONFIG.read() {
+        if let Some(global_config) = cache.as_ref() {
+            return global_config.clone();
+        }
+    }
+    let global_config = mix_config::load_or_default::<GlobalConfig>("global");
+    set_global_config(global_config.clone());
+    global_config
 }
-use crate::mix_scheduler::{Job, JobScheduler};
-use cron::Schedule;
+
+///global.yml无法完整解析时，仍尝试读取`strict-config`
+fn is_strict(path: &Path) -> bool {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Strict {
+        #[serde(default)]
+        strict_config: bool,
+    }
+    mix_config::read::<Strict>(&path.to_string_lossy()).map(|s| s.strict_config).unwrap_or(false)
+}
+
+/// 提交配置错误，日志内容以错误码开头，`raw_data`为错误详情
+pub fn report_config_error(e: &ConfigError, agent_name: &str) {
+    error!("{}", e);
+    let tags = vec!["agent-desc|配置校验".to_owned()];
+    let log = init_log("agent", &format!("{}:{}", e.code(), e), LogLevel::Error, Box::new(tags), e, agent_name);
+    post_log(&log);
+}
+
+pub fn set_global_config(global_config: GlobalConfig) {
+    if let Ok(mut cache) = GLOBAL_CONFIG.write() {
+        *cache = Some(global_config);
+    }
+}
+use crate::mix_scheduler::{Job, JobScheduler, Trigger};
 use lazy_static::lazy_static;
-use std::sync::Mutex;
+use std::sync::RwLock;
 lazy_static! {
-    static ref mutex_client: Mutex<Client> = Mutex::new(reqwest::blocking::Client::new());
+    ///reqwest的Client内部有连接池且可跨线程共享，不加锁，避免重试等待时阻塞其他探针的提交
+    static ref HTTP_CLIENT: Client = reqwest::blocking::Client::new();
+    static ref GLOBAL_CONFIG: RwLock<Option<GlobalConfig>> = RwLock::new(None);
 }
 
 pub fn post_log<T: Serialize>(log: &Log<T>) {
     //let client = reqwest::blocking::Client::new();
-    let global_config = mix_config::load::<GlobalConfig>("global");
+    let global_config = get_global_config();
 
     if global_config.mix_endpoint == "" {
         error!("mix_endpoint配置无效")
@@ -240,41 +393,87 @@ pub fn post_log<T: Serialize>(log: &Log<T>) {
 
     let json = serde_json::to_string(&log);
     if global_config.print_log_json {
-        info!("{}", json.expect("解析失败"));
+        info!("{}", json.as_ref().expect("解析失败"));
     }
-    let mut mix_endpoint = global_config.mix_endpoint;
+
+    let body = json.expect("解析失败");
+    let record = Record {
+        agent_name: &log.source.name,
+        category: &log.category,
+        level: &log.level,
+        time: log.time,
+        body: &body,
+    };
+    mix_sink::write_all(&global_config, &record);
+}
+
+fn collect_endpoint(global_config: &GlobalConfig) -> String {
+    let mut mix_endpoint = global_config.mix_endpoint.clone();
     mix_endpoint.push_str("/mix/api/v1/monitor/collect");
-    //info!("{}", mix_endpoint);
-
-    let client_guard = mutex_client.lock();
-    match client_guard {
-        Ok(client) => {
-            if global_config.push_log {
-                let res = client.post(mix_endpoint.as_str()).json(log).timeout(Duration::from_millis(global_config.timeout)).send();
-                response_handler(log.category.clone(), res);
-            }
-        }
-        Err(e) => {
-            error!("get reqwest client error:{}", e.to_string());
-        }
+    mix_endpoint
+}
+
+///逐条提交日志，临时故障按重试策略重试，仍失败时暂存
+///
+///该探针有暂存的日志时先补发，未补发完时新日志追加到暂存末尾，保证按产生顺序送达
+fn deliver(client: &Client, global_config: &GlobalConfig, agent_name: &str, category: &str, body: &str) -> Delivery {
+    if global_config.spool.enabled && !replay_spool(client, global_config, agent_name) {
+        spool_log(global_config, agent_name, category, body);
+        return Delivery::Unavailable;
+    }
+
+    let mix_endpoint = collect_endpoint(global_config);
+    let timeout = Duration::from_millis(global_config.timeout);
+    let retry = &global_config.retry;
+    let key = global_config.mix_endpoint_key.as_str();
+    if key.is_empty() {
+        warn!("未配置mix-endpoint-key，日志将不签名提交");
+    }
+
+    let delivery = retry.run(category, || {
+        let res = signed_post(client, mix_endpoint.as_str(), body.as_bytes(), key).timeout(timeout).send();
+        retry.classify(category, res)
+    });
+    if delivery == Delivery::Unavailable {
+        spool_log(global_config, agent_name, category, body);
     }
+    delivery
 }
 
-fn response_handler(category: String, res: Result<Response, Error>) {
-    match res {
-        Ok(res) => {
-            if res.status().is_success() {
-                info!("日志提交成功, 类型:{}, 响应:{}", category, res.text().expect(""));
-            } else {
-                warn!("日志提交异常, 类型:{}, 响应:{}", category, res.text().expect(""));
-            }
-        }
-        Err(e) => {
-            error!("日志提交失败, 类型:{}, 错误:{}", category, e.to_string());
-        }
+fn spool_log(global_config: &GlobalConfig, agent_name: &str, category: &str, body: &str) {
+    if !global_config.spool.enabled {
+        return;
+    }
+    match Spool::open(agent_name, &global_config.spool).push(category, body) {
+        Ok(_) => info!("日志已暂存, 类型:{}", category),
+        Err(e) => error!("日志暂存失败, 类型:{}, 错误:{}", category, e),
     }
 }
 
+///每次补发暂存日志的最大条数，避免积压较多时长时间阻塞新日志的提交
+const REPLAY_LIMIT: usize = 100;
+
+///端点恢复后按顺序补发暂存的日志，补发时不重试，遇到故障即停止等待下次补发；返回暂存是否已补发完
+fn replay_spool(client: &Client, global_config: &GlobalConfig, agent_name: &str) -> bool {
+    let spool = Spool::open(agent_name, &global_config.spool);
+    if spool.is_empty() {
+        return true;
+    }
+
+    let mix_endpoint = collect_endpoint(global_config);
+    let timeout = Duration::from_millis(global_config.timeout);
+    let key = global_config.mix_endpoint_key.as_str();
+    let result = spool.replay(REPLAY_LIMIT, |entry| {
+        let res = signed_post(client, mix_endpoint.as_str(), entry.body.as_bytes(), key).timeout(timeout).send();
+        global_config.retry.classify(&entry.category, res)
+    });
+    match result {
+        Ok(count) => info!("暂存日志补发完成, 条数:{}", count),
+        Err(e) => error!("暂存日志补发失败, 错误:{}", e),
+    }
+    spool.is_empty()
+}
+
 pub trait StripBom {
     fn strip_bom(&self) -> &str;
 }
@@ -306,3 +505,14 @@ pub fn test_get_local_ip() {
     let local_ip = get_local_ip();
     println!("{}", local_ip);
 }
+
+#[test]
+pub fn test_validate_splay() {
+    let mut global_config = GlobalConfig::default();
+    global_config.scheduler.splay = mix_host::MAX_SPLAY;
+    assert!(global_config.validate("global.yml").is_ok());
+    global_config.scheduler.splay = u64::MAX;
+    let e = global_config.validate("global.yml").unwrap_err();
+    assert_eq!(e.path, "scheduler.splay");
+    assert_eq!(e.code(), "10004");
+}
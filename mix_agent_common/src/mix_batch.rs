@@ -0,0 +1,330 @@
+//! 日志批量提交
+//!
+//! 开启 `batch.enabled` 后，`post_log` 不再逐条提交，而是先缓存，累计 `batch.max-items` 条
+//! 或最早一条缓存超过 `batch.max-wait` 秒后，合并为一个json数组(默认gzip压缩)一次提交。
+//! 端点返回415(不支持的数据格式)时，之后 `batch.max-wait` 的10倍时间内改为逐条提交，到期后再尝试批量提交；其他4xx时本批日志改为逐条提交，只丢弃被拒绝的日志。
+//! 程序退出时缓存中未提交的日志写入暂存，下次启动后补发。
+
+use crate::mix_retry::Delivery;
+use crate::mix_sign::signed_post;
+use crate::{collect_endpoint, deliver, get_global_config, replay_spool, spool_log, GlobalConfig, HTTP_CLIENT};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_ENCODING;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct BatchConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    ///累计多少条日志提交一次
+    #[serde(default = "default_max_items")]
+    pub max_items: usize,
+    ///日志最长缓存时间，秒
+    #[serde(default = "default_max_wait")]
+    pub max_wait: u64,
+    ///是否gzip压缩
+    #[serde(default = "default_gzip")]
+    pub gzip: bool,
+}
+
+fn default_enabled() -> bool {
+    false
+}
+
+fn default_max_items() -> usize {
+    10
+}
+
+fn default_max_wait() -> u64 {
+    60
+}
+
+fn default_gzip() -> bool {
+    true
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            enabled: default_enabled(),
+            max_items: default_max_items(),
+            max_wait: default_max_wait(),
+            gzip: default_gzip(),
+        }
+    }
+}
+
+/// 等待批量提交的一条日志
+#[derive(Debug, Clone)]
+pub struct Pending {
+    pub agent_name: String,
+    pub category: String,
+    pub body: String,
+}
+
+#[derive(Default)]
+struct Buffer {
+    items: Vec<Pending>,
+    since: Option<Instant>,
+}
+
+impl Buffer {
+    fn take(&mut self) -> Vec<Pending> {
+        self.since = None;
+        std::mem::take(&mut self.items)
+    }
+}
+
+lazy_static! {
+    static ref BUFFER: Mutex<Buffer> = Mutex::new(Buffer::default());
+    ///端点返回415后，在此时间之前逐条提交
+    static ref UNSUPPORTED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+}
+static FLUSHER: Once = Once::new();
+
+///端点返回415后逐条提交的时长，为`max-wait`的倍数，可能只是代理临时返回或端点之后升级
+const UNSUPPORTED_BACKOFF: u64 = 10;
+
+/// 端点返回415后的一段时间内不再批量提交
+fn mark_unsupported(max_wait: u64) {
+    let backoff = Duration::from_secs(max_wait.max(1) * UNSUPPORTED_BACKOFF);
+    if let Ok(mut until) = UNSUPPORTED_UNTIL.lock() {
+        *until = Some(Instant::now() + backoff);
+    }
+    warn!("端点不支持批量提交(415)，{}秒内改为逐条提交", backoff.as_secs());
+}
+
+fn is_unsupported() -> bool {
+    match UNSUPPORTED_UNTIL.lock() {
+        Ok(mut until) => match *until {
+            Some(t) if Instant::now() < t => true,
+            Some(_) => {
+                info!("重新尝试批量提交");
+                *until = None;
+                false
+            }
+            None => false,
+        },
+        Err(_) => false,
+    }
+}
+
+/// 缓存一条日志，达到条数上限时立即提交
+pub fn enqueue(global_config: &GlobalConfig, agent_name: &str, category: &str, body: String) {
+    let pending = Pending {
+        agent_name: agent_name.to_string(),
+        category: category.to_string(),
+        body,
+    };
+
+    if is_unsupported() {
+        send_single(global_config, vec![pending]);
+        return;
+    }
+
+    FLUSHER.call_once(|| {
+        thread::spawn(|| loop {
+            thread::sleep(Duration::from_secs(1));
+            let global_config = get_global_config();
+            let max_wait = Duration::from_secs(global_config.batch.max_wait);
+            let items = match BUFFER.lock() {
+                Ok(mut buffer) if buffer.since.map(|s| s.elapsed() >= max_wait).unwrap_or(false) => buffer.take(),
+                _ => continue,
+            };
+            send(&global_config, items);
+        });
+    });
+
+    let items = match BUFFER.lock() {
+        Ok(mut buffer) => {
+            buffer.since.get_or_insert_with(Instant::now);
+            buffer.items.push(pending);
+            if buffer.items.len() < global_config.batch.max_items {
+                return;
+            }
+            buffer.take()
+        }
+        Err(e) => {
+            error!("get batch buffer error:{}", e);
+            return;
+        }
+    };
+    send(global_config, items);
+}
+
+/// 立即提交缓存中的全部日志
+pub fn flush() {
+    let items = match BUFFER.lock() {
+        Ok(mut buffer) => buffer.take(),
+        Err(_) => return,
+    };
+    if !items.is_empty() {
+        send(&get_global_config(), items);
+    }
+}
+
+/// 程序退出前调用，缓存中的日志写入暂存，未开启暂存时立即提交
+pub fn shutdown() {
+    let items = match BUFFER.lock() {
+        Ok(mut buffer) => buffer.take(),
+        Err(_) => return,
+    };
+    if items.is_empty() {
+        return;
+    }
+
+    let global_config = get_global_config();
+    if !global_config.spool.enabled {
+        send(&global_config, items);
+        return;
+    }
+    for item in items.iter() {
+        spool_log(&global_config, &item.agent_name, &item.category, &item.body);
+    }
+    info!("程序退出，未提交的批量日志已暂存, 条数:{}", items.len());
+}
+
+/// 将多条日志合并为json数组，按配置gzip压缩
+pub fn encode(items: &[Pending], gzip: bool) -> Vec<u8> {
+    let bodies: Vec<&str> = items.iter().map(|i| i.body.as_str()).collect();
+    let json = format!("[{}]", bodies.join(","));
+    if !gzip {
+        return json.into_bytes();
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(json.as_bytes()).expect("写入内存不会失败");
+    encoder.finish().expect("写入内存不会失败")
+}
+
+fn send(global_config: &GlobalConfig, items: Vec<Pending>) {
+    if items.is_empty() {
+        return;
+    }
+
+    let client: &Client = &HTTP_CLIENT;
+
+    //先补发各探针暂存的日志，未补发完的探针的日志追加到暂存末尾，保证按产生顺序送达
+    let items = if global_config.spool.enabled {
+        let mut agents: Vec<&str> = items.iter().map(|i| i.agent_name.as_str()).collect();
+        agents.sort_unstable();
+        agents.dedup();
+        let behind: Vec<String> = agents
+            .into_iter()
+            .filter(|agent_name| !replay_spool(client, global_config, agent_name))
+            .map(|agent_name| agent_name.to_string())
+            .collect();
+        let (spooled, items): (Vec<Pending>, Vec<Pending>) = items.into_iter().partition(|i| behind.contains(&i.agent_name));
+        for item in spooled.iter() {
+            spool_log(global_config, &item.agent_name, &item.category, &item.body);
+        }
+        if items.is_empty() {
+            return;
+        }
+        items
+    } else {
+        items
+    };
+
+    let mix_endpoint = collect_endpoint(global_config);
+    let timeout = Duration::from_millis(global_config.timeout);
+    let key = global_config.mix_endpoint_key.as_str();
+    let gzip = global_config.batch.gzip;
+    let payload = encode(&items, gzip);
+    let category = format!("batch({})", items.len());
+
+    let retry = &global_config.retry;
+    let mut unsupported = false;
+    let delivery = retry.run(&category, || {
+        let mut request = signed_post(client, mix_endpoint.as_str(), &payload, key).timeout(timeout);
+        if gzip {
+            request = request.header(CONTENT_ENCODING, "gzip");
+        }
+        let res = request.send();
+        if let Ok(r) = &res {
+            if r.status() == StatusCode::UNSUPPORTED_MEDIA_TYPE {
+                unsupported = true;
+                return Delivery::Rejected;
+            }
+        }
+        retry.classify(&category, res)
+    });
+
+    if unsupported {
+        mark_unsupported(global_config.batch.max_wait);
+        deliver_each(client, global_config, items);
+        return;
+    }
+
+    match delivery {
+        Delivery::Delivered => {}
+        //可能只是其中部分日志无效，逐条提交以免丢弃其余日志
+        Delivery::Rejected => {
+            warn!("批量日志被拒绝，改为逐条提交, 条数:{}", items.len());
+            deliver_each(client, global_config, items);
+        }
+        Delivery::Unavailable => {
+            for item in items.iter() {
+                spool_log(global_config, &item.agent_name, &item.category, &item.body);
+            }
+        }
+    }
+}
+
+fn send_single(global_config: &GlobalConfig, items: Vec<Pending>) {
+    deliver_each(&HTTP_CLIENT, global_config, items);
+}
+
+fn deliver_each(client: &Client, global_config: &GlobalConfig, items: Vec<Pending>) {
+    for item in items.iter() {
+        deliver(client, global_config, &item.agent_name, &item.category, &item.body);
+    }
+    info!("逐条提交完成, 条数:{}", items.len());
+}
+
+#[test]
+fn test_encode_gzip() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let items = vec![
+        Pending {
+            agent_name: "mix_agent_cpu".to_string(),
+            category: "cpu".to_string(),
+            body: "{\"n\":1}".to_string(),
+        },
+        Pending {
+            agent_name: "mix_agent_memory".to_string(),
+            category: "memory".to_string(),
+            body: "{\"n\":2}".to_string(),
+        },
+    ];
+    assert_eq!(encode(&items, false), b"[{\"n\":1},{\"n\":2}]".to_vec());
+
+    let mut json = String::new();
+    GzDecoder::new(&encode(&items, true)[..]).read_to_string(&mut json).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value.as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_unsupported_backoff() {
+    assert!(!is_unsupported());
+    mark_unsupported(60);
+    assert!(is_unsupported());
+
+    //到期后重新尝试批量提交
+    *UNSUPPORTED_UNTIL.lock().unwrap() = Some(Instant::now() - Duration::from_secs(1));
+    assert!(!is_unsupported());
+    assert_eq!(*UNSUPPORTED_UNTIL.lock().unwrap(), None);
+}
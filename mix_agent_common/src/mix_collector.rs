@@ -0,0 +1,164 @@
+//! 采集与提交分离
+//!
+//! 探针实现 `Collector`，只负责按配置采集并返回数据，便于单元测试及复用；
+//! 调度、`init_log` 及 `post_log` 统一由 `Host::add_collector` 处理。
+
+use crate::mix_config::AgentConfig;
+use crate::{init_log, post_log, Log, LogLevel};
+use log::warn;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// 采集失败，以`agent`分类提交，内容一般以错误码开头
+#[derive(Debug)]
+pub struct CollectError {
+    pub level: LogLevel,
+    pub content: String,
+    ///随日志提交的数据
+    pub data: Value,
+}
+
+impl CollectError {
+    pub fn warn(content: &str) -> CollectError {
+        CollectError {
+            level: LogLevel::Warn,
+            content: content.to_string(),
+            data: Value::String(String::new()),
+        }
+    }
+
+    pub fn error(content: &str) -> CollectError {
+        CollectError {
+            level: LogLevel::Error,
+            ..CollectError::warn(content)
+        }
+    }
+
+    /// 附带已采集到的部分数据
+    pub fn with_data<T: Serialize>(self, data: &T) -> CollectError {
+        CollectError {
+            data: serde_json::to_value(data).unwrap_or_default(),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for CollectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.content)
+    }
+}
+
+/// 探针的采集部分
+pub trait Collector {
+    type Config: AgentConfig;
+    /// 为`Option`时，`None`表示本次不提交，用于缓冲多次采集后一起提交的探针
+    type Output: Serialize;
+
+    /// 探针名称，同时为配置文件名及日志来源
+    fn agent_name(&self) -> &str;
+
+    /// 日志分类，如cpu、memory
+    fn category(&self) -> &str;
+
+    /// 日志标签，如`agent-desc|cpu使用率`
+    fn tags(&self) -> Vec<String>;
+
+    /// 采集成功时的日志级别
+    fn level(&self) -> LogLevel {
+        LogLevel::Info
+    }
+
+    /// 按配置采集一次
+    fn sample(&mut self, config: &Self::Config) -> Result<Self::Output, CollectError>;
+}
+
+/// 采集一次并生成待提交的日志，采集失败时生成`agent`分类的日志，本次无需提交时返回None
+pub fn collect_log<C: Collector>(collector: &mut C, config: &C::Config) -> Option<Log<Value>> {
+    collect(collector, config).0
+}
+
+/// 采集一次并提交日志，采集失败时返回失败原因
+pub fn collect_and_post<C: Collector>(collector: &mut C, config: &C::Config) -> Result<(), String> {
+    let (log, result) = collect(collector, config);
+    if let Some(log) = log {
+        post_log(&log);
+    }
+    result
+}
+
+fn collect<C: Collector>(collector: &mut C, config: &C::Config) -> (Option<Log<Value>>, Result<(), String>) {
+    let tags = Box::new(collector.tags());
+    match collector.sample(config) {
+        Ok(output) => match serde_json::to_value(&output).unwrap_or_default() {
+            Value::Null => (None, Ok(())),
+            data => (Some(init_log(collector.category(), "", collector.level(), tags, data, collector.agent_name())), Ok(())),
+        },
+        Err(e) => {
+            warn!("{}", e);
+            (Some(init_log("agent", &e.content, e.level, tags, e.data, collector.agent_name())), Err(e.content))
+        }
+    }
+}
+
+#[cfg(test)]
+struct Counter {
+    count: u32,
+}
+
+#[cfg(test)]
+impl Collector for Counter {
+    type Config = crate::mix_reload::TestConfig;
+    type Output = Option<Vec<String>>;
+
+    fn agent_name(&self) -> &str {
+        "mix_agent_counter"
+    }
+
+    fn category(&self) -> &str {
+        "counter"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|计数".to_string()]
+    }
+
+    fn sample(&mut self, config: &Self::Config) -> Result<Self::Output, CollectError> {
+        self.count += 1;
+        if config.target.is_empty() {
+            return Err(CollectError::warn("90001:未配置`target`").with_data(&self.count));
+        }
+        if config.target.iter().any(|t| t.is_empty()) {
+            return Ok(None);
+        }
+        Ok(Some(config.target.iter().map(|t| format!("{}#{}", t, self.count)).collect()))
+    }
+}
+
+#[test]
+fn test_collect_log() {
+    use crate::mix_config::MixConfig;
+    use crate::mix_reload::TestConfig;
+
+    let mut counter = Counter { count: 0 };
+    let mut config = TestConfig::new();
+    config.target = vec!["a".to_string()];
+    let log = collect_log(&mut counter, &config).unwrap();
+    assert_eq!(log.category, "counter");
+    assert_eq!(log.level, "info");
+    assert_eq!(log.content, "");
+    assert_eq!(log.raw_data, serde_json::json!(["a#1"]));
+    assert_eq!(log.source.name, "mix_agent_counter");
+    assert_eq!(*log.tags, vec!["agent-desc|计数"]);
+
+    let log = collect_log(&mut counter, &TestConfig::new()).unwrap();
+    assert_eq!(log.category, "agent");
+    assert_eq!(log.level, "warn");
+    assert_eq!(log.content, "90001:未配置`target`");
+    assert_eq!(log.raw_data, serde_json::json!(2));
+
+    //空的`target`以`None`表示本次不提交
+    config.target = vec![String::new()];
+    assert!(collect_log(&mut counter, &config).is_none());
+}
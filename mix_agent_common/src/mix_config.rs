@@ -1,3 +1,6 @@
+use crate::mix_override::{self, config_dir, log_dir};
+use crate::mix_remote;
+use crate::mix_secret;
 use config::{Config, File, FileFormat};
 use log::{error, LevelFilter};
 use log4rs::append::console::ConsoleAppender;
@@ -8,29 +11,51 @@ use log4rs::append::rolling_file::RollingFileAppender;
 use log4rs::config::Config as logConfig;
 use log4rs::config::{Appender, Root};
 use log4rs::encode::pattern::PatternEncoder;
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::env;
+use std::error::Error;
+use std::fmt::{self, Debug};
 use std::path::Path;
 
+/// `cron` 以此开头时按固定间隔执行，如`@every 30s`，配置项`every`即转换为此形式
+pub const EVERY: &str = "@every";
+
 pub trait MixConfig {
     fn new() -> Self;
 }
 
-pub fn init_logger(name: &str) {
-    let config_dir = get_current_dir();
-    env::set_current_dir(&config_dir).expect("error : set current directory ");
+/// 探针配置，由 `Monitor::begin_with_config` 监听配置文件变化并热加载
+pub trait AgentConfig: DeserializeOwned + MixConfig + Debug {
+    /// cron表达式，或`@every 30s`形式的执行间隔
+    fn cron(&self) -> &str;
+
+    /// `cron`的时区，IANA名称如`Asia/Shanghai`，为空时使用全局配置`scheduler.timezone`
+    fn timezone(&self) -> &str {
+        ""
+    }
+
+    /// 校验配置，校验失败的配置不会被加载；`cron` 已统一校验，无需重复
+    ///
+    /// 校验失败时返回配置项路径(如`target-source.ip`)及原因
+    fn validate(&self) -> Result<(), (String, String)> {
+        Ok(())
+    }
+}
 
+/// 初始化日志，日志目录见 `mix_override::log_dir`
+pub fn init_logger(name: &str) {
+    let log_dir = log_dir();
     let tpl = "{d} {h({l})} {M}[{L}] - {m}{n}";
     let stdout = ConsoleAppender::builder().encoder(Box::new(PatternEncoder::new(tpl))).build();
 
     let window_size = 5; // log0, log1, log2
-    let mut pattern = format!("log/{}", name);
-    pattern.push_str("_{}.gz");
+    let pattern = log_dir.join(format!("{}_{{}}.gz", name)).to_string_lossy().to_string();
     let fixed_window_roller = FixedWindowRoller::builder().build(pattern.as_str(), window_size).unwrap();
     let size_limit = 10 * 1024 * 1024; // 10mb as max log file size to roll
     let size_trigger = SizeTrigger::new(size_limit);
     let compound_policy = CompoundPolicy::new(Box::new(size_trigger), Box::new(fixed_window_roller));
-    let file = RollingFileAppender::builder().encoder(Box::new(PatternEncoder::new(tpl))).build(format!("log/{}.log", name), Box::new(compound_policy)).unwrap();
+    let file = RollingFileAppender::builder().encoder(Box::new(PatternEncoder::new(tpl))).build(log_dir.join(format!("{}.log", name)), Box::new(compound_policy)).unwrap();
 
     let config = logConfig::builder()
         .appender(Appender::builder().build("stdout", Box::new(stdout)))
@@ -46,25 +71,161 @@ pub fn get_current_dir() -> String {
     String::from(work_dir.to_str().expect("error : Path to str"))
 }
 
-pub fn load<'a, T: Deserialize<'a> + MixConfig>(config_name: &str) -> T where {
-    let mut exe_path = get_current_dir();
+/// 配置文件路径：配置目录/<config_name>.yml，配置目录见 `mix_override::config_dir`
+pub fn config_path(config_name: &str) -> String {
+    config_dir().join(format!("{}.yml", config_name)).to_string_lossy().to_string()
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigErrorKind {
+    /// 文件不是合法的yaml
+    Syntax,
+    /// 配置项类型错误、缺失或校验失败
+    Field,
+}
+
+/// 配置错误，包含文件、配置项路径及原因
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub kind: ConfigErrorKind,
+    pub file: String,
+    ///配置项路径，如`sinks[1].address`，无法定位时为空
+    pub path: String,
+    pub reason: String,
+}
+
+impl ConfigError {
+    pub fn field(file: &str, path: &str, reason: &str) -> ConfigError {
+        ConfigError {
+            kind: ConfigErrorKind::Field,
+            file: file.to_string(),
+            path: path.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn from_config(file: &str, e: config::ConfigError) -> ConfigError {
+        use config::ConfigError::*;
+        match e {
+            FileParse { cause, .. } => ConfigError {
+                kind: ConfigErrorKind::Syntax,
+                file: file.to_string(),
+                path: String::new(),
+                reason: cause.to_string(),
+            },
+            Type { unexpected, expected, key, .. } => ConfigError::field(file, &key.unwrap_or_default(), &format!("类型错误，应为{}，实际为{}", expected, unexpected)),
+            NotFound(key) => ConfigError::field(file, &key, "缺少配置项"),
+            Message(m) => match m.strip_prefix("missing field `").and_then(|k| k.split('`').next()) {
+                Some(key) => ConfigError::field(file, key, "缺少配置项"),
+                None => ConfigError::field(file, "", &m),
+            },
+            other => ConfigError::field(file, "", &other.to_string()),
+        }
+    }
+
+    /// 日志中使用的错误码，10003：文件无法解析，10004：配置项无效
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            ConfigErrorKind::Syntax => "10003",
+            ConfigErrorKind::Field => "10004",
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}: {}", self.file, self.reason)
+        } else {
+            write!(f, "{}: `{}` {}", self.file, self.path, self.reason)
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+fn config_name(path: &str) -> String {
+    Path::new(path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+fn parse<T: DeserializeOwned>(path: &str, file: bool, remote: Option<&serde_json::Value>) -> Result<T, ConfigError> {
     let mut c = Config::default();
-    exe_path.push_str("/config/");
-    exe_path.push_str(config_name);
-    exe_path.push_str(".yml");
-
-    let final_path = exe_path.as_str();
-    if Path::new(final_path).exists() {
-        c.merge(File::new(final_path, FileFormat::Yaml));
-        let config = c.try_into::<T>();
-        return match config {
-            Ok(c) => c,
-            Err(e) => {
-                error!("{:?}", e.to_string());
-                T::new()
-            }
-        };
+    if file {
+        //有远程配置时本地配置文件可以不存在
+        c.merge(File::new(path, FileFormat::Yaml).required(remote.is_none())).map_err(|e| ConfigError::from_config(path, e))?;
     }
+    if let Some(remote) = remote {
+        c.merge(File::from_str(&remote.to_string(), FileFormat::Json)).map_err(|e| ConfigError::from_config(path, e))?;
+    }
+    mix_override::apply(&mut c, &mix_override::current(&config_name(path))).map_err(|e| ConfigError::from_config(path, e))?;
+    mix_secret::apply(&mut c).map_err(|(key, reason)| ConfigError::field(path, &key, &reason))?;
+    //`every: 30s`可代替`cron`，同时配置时以`every`为准
+    if let Ok(every) = c.get_str("every") {
+        c.set("cron", format!("{} {}", EVERY, every)).map_err(|e| ConfigError::from_config(path, e))?;
+    }
+    c.try_into::<T>().map_err(|e| ConfigError::from_config(path, e))
+}
+
+/// 读取并解析配置文件，合并缓存的远程配置，再以命令行及环境变量覆盖，最后替换密文及引用
+pub fn read<T: DeserializeOwned>(path: &str) -> Result<T, ConfigError> {
+    let cache = mix_remote::Cache::load(Path::new(path));
+    parse(path, true, cache.as_ref().map(|c| &c.config))
+}
+
+/// 以给定的远程配置代替缓存读取，用于校验拉取到的配置
+pub fn read_remote<T: DeserializeOwned>(path: &str, remote: &serde_json::Value) -> Result<T, ConfigError> {
+    parse(path, true, Some(remote))
+}
+
+/// 加载配置，配置文件及远程配置均不存在时仅使用命令行及环境变量，均未设置时使用默认配置
+pub fn load<T: DeserializeOwned + MixConfig>(config_name: &str) -> Result<T, ConfigError> {
+    load_path(Path::new(&config_path(config_name)))
+}
+
+/// 同 `load`，使用指定的配置文件路径
+pub fn load_path<T: DeserializeOwned + MixConfig>(path: &Path) -> Result<T, ConfigError> {
+    let final_path = path.to_string_lossy();
+    if path.exists() || mix_remote::cache_path(path).exists() {
+        return read::<T>(&final_path);
+    }
+    if !mix_override::current(&config_name(&final_path)).is_empty() {
+        return parse(&final_path, false, None);
+    }
+
+    Ok(T::new())
+}
+
+/// 加载配置，失败时记录错误并使用默认配置
+pub fn load_or_default<T: DeserializeOwned + MixConfig>(config_name: &str) -> T {
+    load::<T>(config_name).unwrap_or_else(|e| {
+        error!("{}", e);
+        T::new()
+    })
+}
+
+#[test]
+fn test_read_config_error() {
+    let dir = env::temp_dir().join(format!("mix_config_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("mix_agent_disk.yml");
+    let file = path.to_str().unwrap();
+
+    std::fs::write(&path, "cron: * 0/30 * * * * ?\nfile_system: [ foo bar, baz ]\n").unwrap();
+    let e = read::<crate::GlobalConfig>(file).unwrap_err();
+    assert_eq!(e.kind, ConfigErrorKind::Syntax);
+    assert_eq!(e.code(), "10003");
+
+    std::fs::write(&path, "customer-id: c\nproject-id: p\nmix-endpoint: http://mix.wushiai.com\nmix-endpoint-key: k\ntimeout: soon\n").unwrap();
+    let e = read::<crate::GlobalConfig>(file).unwrap_err();
+    assert_eq!(e.kind, ConfigErrorKind::Field);
+    assert_eq!(e.path, "timeout");
+    assert_eq!(e.code(), "10004");
+
+    std::fs::write(&path, "customer-id: c\nmix-endpoint: http://mix.wushiai.com\nmix-endpoint-key: k\n").unwrap();
+    let e = read::<crate::GlobalConfig>(file).unwrap_err();
+    assert_eq!(e.path, "project-id");
+    assert_eq!(e.to_string(), format!("{}: `project-id` 缺少配置项", file));
 
-    return T::new();
+    std::fs::remove_dir_all(&dir).unwrap();
 }
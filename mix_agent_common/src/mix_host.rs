@@ -0,0 +1,540 @@
+//! 探针宿主
+//!
+//! 在一个 `JobScheduler` 中运行一个或多个探针：启动时加载并校验全局配置及各探针配置，
+//! 运行期间监听配置文件变化、拉取远程配置，按探针分别替换配置及调度计划。
+//! 各探针实现 `Collector` 并通过 `register` 添加到宿主，单独的探针程序只托管自身，`mix_agent` 按全局配置 `agents` 托管多个探针并共用 `sysinfo::System`。
+//!
+//! 采集任务在工作线程中执行，不阻塞调度及其他探针：到达执行时间时上次执行仍未结束则跳过本次，
+//! 执行时间超过 `scheduler.timeout` 时报告超时，均提交一条`agent`类别的日志。
+
+use crate::mix_collector::{self, Collector};
+use crate::mix_config::{self, config_path, AgentConfig, ConfigError};
+use crate::mix_reload::{self, Watcher};
+use crate::mix_remote::{self, Poller};
+use crate::mix_scheduler::{parse_timezone, Job, JobEvent, JobScheduler, JobStats, RunResult, Trigger, Uuid};
+use crate::{get_agent_name, get_global_config, init_log, is_strict, mix_batch, mix_metrics, post_log, report_config_error, set_global_config, GlobalConfig, Log, LogLevel};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+///`scheduler.splay`的上限，秒
+pub const MAX_SPLAY: u64 = 86400;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct SchedulerConfig {
+    ///单次采集的超时时间，秒，0为不限制。超时只报告，采集仍会继续直至结束
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    ///上次采集未结束时跳过本次，为false时本次等待上次结束后执行，最多等待一次，已有等待时跳过
+    #[serde(default = "default_skip_if_running")]
+    pub skip_if_running: bool,
+    ///随机延迟执行的最大秒数，避免大量主机同时执行，0为不延迟，最大`MAX_SPLAY`。每个任务的延迟随机选定后保持不变
+    #[serde(default)]
+    pub splay: u64,
+    ///cron的时区，IANA名称如`Asia/Shanghai`，为空时使用本机时区。探针配置中的`timezone`优先
+    #[serde(default)]
+    pub timezone: String,
+    ///提交`agent-health`调度状态的间隔，秒，0为不提交
+    #[serde(default = "default_health_interval")]
+    pub health_interval: u64,
+}
+
+fn default_timeout() -> u64 {
+    60
+}
+
+fn default_skip_if_running() -> bool {
+    true
+}
+
+fn default_health_interval() -> u64 {
+    300
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            timeout: default_timeout(),
+            skip_if_running: default_skip_if_running(),
+            splay: 0,
+            timezone: String::new(),
+            health_interval: default_health_interval(),
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// 时区已由`GlobalConfig::validate`校验，为空或无效时使用本机时区
+    pub fn apply(&self, job: &mut Job) {
+        job.timeout(if self.timeout == 0 { None } else { Some(Duration::from_secs(self.timeout)) });
+        job.skip_if_running(self.skip_if_running);
+        job.splay(Duration::from_secs(self.splay));
+        job.timezone(parse_timezone(&self.timezone).ok());
+    }
+}
+
+/// 提交配置加载结果，内容以错误码开头；与调度事件一样不阻塞调度线程
+fn report(content: String, level: LogLevel, agent_name: &str) {
+    let tags = vec!["agent-desc|配置热加载".to_owned()];
+    post_async(init_log("agent", &content, level, Box::new(tags), json!(""), agent_name));
+}
+
+lazy_static! {
+    static ref REPORTER: Mutex<Sender<Log<Value>>> = {
+        let (sender, receiver) = mpsc::channel::<Log<Value>>();
+        thread::spawn(move || {
+            for log in receiver {
+                post_log(&log);
+            }
+        });
+        Mutex::new(sender)
+    };
+}
+
+/// 交由单独的线程按顺序提交，提交较慢(如重试)时不阻塞调度线程
+fn post_async(log: Log<Value>) {
+    let sent = match REPORTER.lock() {
+        Ok(sender) => sender.send(log).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = sent {
+        error!("调度日志提交失败:{}", e);
+    }
+}
+
+/// 提交跳过及超时事件
+fn report_event(event: &JobEvent, agent_name: &str) {
+    let (content, data) = match event {
+        JobEvent::Skipped { running, .. } => (format!("10005:上次采集已执行{}秒仍未结束，跳过本次", running.as_secs()), json!({ "running": running.as_millis() as u64 })),
+        JobEvent::TimedOut { elapsed, .. } => (format!("10006:采集超时，已执行{}秒", elapsed.as_secs()), json!({ "elapsed": elapsed.as_millis() as u64 })),
+    };
+    warn!("{} {}", agent_name, content);
+    let tags = vec!["agent-desc|任务调度".to_owned()];
+    post_async(init_log("agent", &content, LogLevel::Warn, Box::new(tags), data, agent_name));
+}
+
+/// 探针的调度状态，未调度(配置无效)或上次采集失败时为warn级别
+fn health_log(agent_name: &str, stats: Option<&JobStats>) -> Log<Value> {
+    let millis = |t: Option<DateTime<Utc>>| t.map(|t| t.timestamp_millis());
+    let (level, data) = match stats {
+        Some(stats) => (
+            if stats.last_error.is_some() { LogLevel::Warn } else { LogLevel::Info },
+            json!({
+                "scheduled": true,
+                "running": stats.running,
+                "runs": stats.runs,
+                "missed_runs": stats.missed_runs,
+                "last_start": millis(stats.last_start),
+                "last_duration": stats.last_duration.map(|d| d.as_millis() as u64),
+                "last_error": stats.last_error,
+                "next_run": millis(stats.next_run),
+            }),
+        ),
+        None => (LogLevel::Warn, json!({ "scheduled": false })),
+    };
+    let tags = vec!["agent-desc|探针健康".to_owned()];
+    init_log("agent-health", "", level, Box::new(tags), data, agent_name)
+}
+
+/// 宿主中的一个探针，屏蔽不同的配置类型
+trait Hosted<'a> {
+    fn name(&self) -> &str;
+    fn path(&self) -> &Path;
+    /// 加载并校验配置，无法加载时使用默认配置，校验通过时返回调度计划
+    fn load(&mut self) -> (Option<Trigger>, Vec<ConfigError>);
+    /// 重新加载配置，失败时保留原配置
+    fn reload(&mut self) -> Result<Trigger, ConfigError>;
+    /// 拉取远程配置，有更新时返回true
+    fn poll(&mut self) -> Result<bool, ConfigError>;
+    /// 在后台拉取远程配置，返回上次拉取的结果
+    fn poll_async(&mut self) -> Result<bool, ConfigError>;
+    /// 替换调度计划并立即执行一次
+    fn schedule(&mut self, sched: &mut JobScheduler<'a>, schedule: Trigger);
+    fn job_id(&self) -> Option<Uuid>;
+}
+
+/// 探针的采集任务，返回采集失败的原因
+type Action<C> = Arc<Mutex<dyn FnMut(&C) -> Result<(), String> + Send>>;
+
+struct Agent<C> {
+    name: String,
+    path: PathBuf,
+    poller: Poller,
+    ///执行时取出当前配置，重新加载时不必等待正在执行的采集
+    config: Arc<Mutex<Arc<C>>>,
+    action: Action<C>,
+    job_id: Option<Uuid>,
+}
+
+impl<'a, C: AgentConfig + Send + Sync + 'static> Hosted<'a> for Agent<C> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&mut self) -> (Option<Trigger>, Vec<ConfigError>) {
+        let mut errors = vec![];
+        let config = mix_config::load_path::<C>(&self.path).unwrap_or_else(|e| {
+            errors.push(e);
+            C::new()
+        });
+        let schedule = match mix_reload::check(&config, &self.path.to_string_lossy()) {
+            Ok(schedule) => {
+                mix_remote::applied(&self.name, &self.path);
+                Some(schedule)
+            }
+            Err(e) => {
+                errors.push(e);
+                None
+            }
+        };
+        *self.config.lock().unwrap() = Arc::new(config);
+        (schedule, errors)
+    }
+
+    fn reload(&mut self) -> Result<Trigger, ConfigError> {
+        let (config, schedule) = mix_reload::reload::<C>(&self.path)?;
+        //配置中可能含有已解密的敏感信息，不记录配置内容
+        info!("配置已重新加载: {}", self.path.display());
+        mix_remote::applied(&self.name, &self.path);
+        *self.config.lock().unwrap() = Arc::new(config);
+        Ok(schedule)
+    }
+
+    fn poll(&mut self) -> Result<bool, ConfigError> {
+        self.poller.poll::<C>(&get_global_config())
+    }
+
+    fn poll_async(&mut self) -> Result<bool, ConfigError> {
+        self.poller.poll_async::<C>(&get_global_config())
+    }
+
+    fn schedule(&mut self, sched: &mut JobScheduler<'a>, schedule: Trigger) {
+        if let Some(job_id) = self.job_id.take() {
+            sched.remove(job_id);
+        }
+        let run = {
+            let config = self.config.clone();
+            let action = self.action.clone();
+            move || {
+                let config = config.lock().unwrap().clone();
+                (action.lock().unwrap_or_else(|e| e.into_inner()))(&config)
+            }
+        };
+        let mut job = Job::spawn(schedule, run);
+        get_global_config().scheduler.apply(&mut job);
+        //运行一次，不用等到cron触发
+        job.run_now();
+        self.job_id = Some(sched.add(job));
+    }
+
+    fn job_id(&self) -> Option<Uuid> {
+        self.job_id
+    }
+}
+
+/// 在一个调度器中运行多个探针
+pub struct Host<'a> {
+    config_dir: Option<PathBuf>,
+    agents: Vec<Box<dyn Hosted<'a> + 'a>>,
+    sched: JobScheduler<'a>,
+    watcher: Option<Watcher>,
+    ///上次提交调度状态的时间
+    health_at: Instant,
+}
+
+impl<'a> Default for Host<'a> {
+    fn default() -> Self {
+        Host::new()
+    }
+}
+
+impl<'a> Host<'a> {
+    /// 使用默认配置目录，见 `mix_override::config_dir`
+    pub fn new() -> Host<'a> {
+        Host {
+            config_dir: None,
+            agents: vec![],
+            sched: JobScheduler::new(),
+            watcher: None,
+            health_at: Instant::now(),
+        }
+    }
+
+    /// 使用指定的配置目录
+    pub fn with_config_dir<P: AsRef<Path>>(config_dir: P) -> Host<'a> {
+        Host {
+            config_dir: Some(config_dir.as_ref().to_path_buf()),
+            ..Host::new()
+        }
+    }
+
+    fn path_of(&self, name: &str) -> PathBuf {
+        match &self.config_dir {
+            Some(dir) => dir.join(format!("{}.yml", name)),
+            None => PathBuf::from(config_path(name)),
+        }
+    }
+
+    /// 添加探针，按其配置文件中的`cron`在工作线程中执行`action`，`action`返回的错误记入调度状态
+    pub fn add<C: AgentConfig + Send + Sync + 'static, R: RunResult, T: FnMut(&C) -> R + Send + 'static>(&mut self, agent_name: &str, mut action: T) {
+        let path = self.path_of(agent_name);
+        let action = move |config: &C| action(config).into_error().map_or(Ok(()), Err);
+        self.agents.push(Box::new(Agent {
+            name: agent_name.to_string(),
+            poller: Poller::new(agent_name, &path),
+            path,
+            config: Arc::new(Mutex::new(Arc::new(C::new()))),
+            action: Arc::new(Mutex::new(action)),
+            job_id: None,
+        }));
+    }
+
+    /// 添加探针，按其配置文件中的`cron`采集并提交日志
+    pub fn add_collector<T: Collector + Send + 'static>(&mut self, mut collector: T)
+    where
+        T::Config: Send + Sync + 'static,
+    {
+        let agent_name = collector.agent_name().to_string();
+        self.add(&agent_name, move |config: &T::Config| mix_collector::collect_and_post(&mut collector, config));
+    }
+
+    /// 已添加的探针名称
+    pub fn agent_names(&self) -> Vec<&str> {
+        self.agents.iter().map(|a| a.name()).collect()
+    }
+
+    /// 校验全部配置并开始调度，开启`strict-config`时配置无效则退出
+    pub fn start(&mut self) {
+        let global_path = self.path_of("global");
+        let mut files = vec![global_path.clone()];
+        files.extend(self.agents.iter().map(|a| a.path().to_path_buf()));
+        self.watcher = Some(Watcher::new(files));
+
+        let mut errors: Vec<(String, ConfigError)> = vec![];
+        let host_name = get_agent_name();
+        let strict = match mix_config::load_path::<GlobalConfig>(&global_path) {
+            Ok(global_config) => {
+                let strict = global_config.strict_config;
+                match global_config.validate(&global_path.to_string_lossy()) {
+                    Ok(_) => set_global_config(global_config),
+                    Err(e) => errors.push((host_name.clone(), e)),
+                }
+                strict
+            }
+            Err(e) => {
+                errors.push((host_name.clone(), e));
+                is_strict(&global_path)
+            }
+        };
+        mix_metrics::serve(&get_global_config().prometheus, &host_name);
+
+        let mut schedules = vec![];
+        for agent in self.agents.iter_mut() {
+            //先拉取远程配置，拉取失败或无效时使用缓存，不影响`strict-config`
+            if let Err(e) = agent.poll() {
+                report_config_error(&e, agent.name());
+            }
+            let (schedule, agent_errors) = agent.load();
+            errors.extend(agent_errors.into_iter().map(|e| (agent.name().to_string(), e)));
+            schedules.push(schedule);
+        }
+        for (agent_name, e) in errors.iter() {
+            report_config_error(e, agent_name);
+        }
+        if strict && !errors.is_empty() {
+            error!("已开启`strict-config`，配置无效，程序退出");
+            mix_batch::flush();
+            process::exit(1);
+        }
+
+        for (agent, schedule) in self.agents.iter_mut().zip(schedules) {
+            match schedule {
+                Some(schedule) => agent.schedule(&mut self.sched, schedule),
+                None => warn!("{}配置无效，等待配置修正后开始执行", agent.name()),
+            }
+        }
+    }
+
+    /// 处理配置变化并执行到期的任务
+    pub fn tick(&mut self) {
+        let global_path = self.path_of("global");
+        let changed = self.watcher.as_mut().map(|w| w.changed()).unwrap_or_default();
+        if changed.contains(&global_path) {
+            let host_name = get_agent_name();
+            match mix_reload::reload_global(&global_path) {
+                Ok(global_config) => {
+                    info!("全局配置已重新加载: {}", global_path.display());
+                    for job_id in self.agents.iter().filter_map(|a| a.job_id()) {
+                        if let Some(job) = self.sched.job_mut(job_id) {
+                            global_config.scheduler.apply(job);
+                        }
+                    }
+                    set_global_config(global_config);
+                    report(format!("10001:配置已重新加载`{}`", global_path.display()), LogLevel::Info, &host_name);
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    report(format!("10002:配置无效，继续使用原配置: {}", e), LogLevel::Error, &host_name);
+                }
+            }
+        }
+
+        for agent in self.agents.iter_mut() {
+            let mut reload = changed.iter().any(|p| p == agent.path());
+            match agent.poll_async() {
+                Ok(updated) => reload |= updated,
+                Err(e) => {
+                    error!("{}", e);
+                    report(format!("10002:远程配置无效，继续使用原配置: {}", e), LogLevel::Error, agent.name());
+                }
+            }
+            if !reload {
+                continue;
+            }
+            match agent.reload() {
+                Ok(schedule) => {
+                    report(format!("10001:配置已重新加载`{}`", agent.path().display()), LogLevel::Info, agent.name());
+                    agent.schedule(&mut self.sched, schedule);
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    report(format!("10002:配置无效，继续使用原配置: {}", e), LogLevel::Error, agent.name());
+                }
+            }
+        }
+
+        for event in self.sched.tick() {
+            let job_id = match &event {
+                JobEvent::Skipped { job_id, .. } | JobEvent::TimedOut { job_id, .. } => *job_id,
+            };
+            if let Some(agent) = self.agents.iter().find(|a| a.job_id() == Some(job_id)) {
+                report_event(&event, agent.name());
+            }
+        }
+
+        let interval = get_global_config().scheduler.health_interval;
+        if interval > 0 && self.health_at.elapsed() >= Duration::from_secs(interval) {
+            self.health_at = Instant::now();
+            self.report_health();
+        }
+    }
+
+    /// 提交各探针的调度状态，服务端据此发现停止采集的探针；在单独的线程中提交，不阻塞调度
+    pub fn report_health(&self) {
+        for agent in self.agents.iter() {
+            let stats = agent.job_id().and_then(|job_id| self.sched.job(job_id)).map(|job| job.stats());
+            post_async(health_log(agent.name(), stats.as_ref()));
+        }
+    }
+
+    /// 开始调度，不再返回
+    pub fn run(mut self) {
+        //收到退出信号(ctrl-c、SIGTERM)时保存批量缓存中的日志
+        if let Err(e) = ctrlc::set_handler(|| {
+            info!("收到退出信号，程序退出");
+            mix_batch::shutdown();
+            process::exit(0);
+        }) {
+            warn!("注册退出信号处理失败: {}", e);
+        }
+        self.start();
+        loop {
+            self.tick();
+            //最多等待500毫秒，以便及时处理配置变化及采集超时
+            thread::sleep(self.sched.time_till_next_job().min(Duration::from_millis(500)))
+        }
+    }
+}
+
+#[test]
+fn test_host_agents() {
+    use crate::mix_reload::TestConfig;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    //等待工作线程执行
+    fn wait_until<F: Fn() -> bool>(host: &mut Host, f: F) {
+        for _ in 0..50 {
+            host.tick();
+            if f() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("mix_host_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("global.yml"), "customer-id: c\nproject-id: p\nmix-endpoint: http://127.0.0.1:9\nmix-endpoint-key: k\nsinks: []\nbatch:\n  enabled: false\n").unwrap();
+    fs::write(dir.join("mix_agent_a.yml"), "cron: \"0 0 0 1 1 ?\"\ntarget: [a]\n").unwrap();
+    fs::write(dir.join("mix_agent_b.yml"), "cron: \"every day\"\n").unwrap();
+
+    let runs_a = Arc::new(AtomicUsize::new(0));
+    let targets_b = Arc::new(Mutex::new(vec![]));
+    let mut host = Host::with_config_dir(&dir);
+    host.add("mix_agent_a", {
+        let runs_a = runs_a.clone();
+        move |_: &TestConfig| {
+            runs_a.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    host.add("mix_agent_b", {
+        let targets_b = targets_b.clone();
+        move |config: &TestConfig| targets_b.lock().unwrap().push(config.target.clone())
+    });
+    assert_eq!(host.agent_names(), vec!["mix_agent_a", "mix_agent_b"]);
+
+    //b的配置无效，不影响a
+    host.start();
+    wait_until(&mut host, || runs_a.load(Ordering::SeqCst) == 1);
+    assert_eq!(runs_a.load(Ordering::SeqCst), 1);
+    assert!(targets_b.lock().unwrap().is_empty());
+
+    //b修正后单独重新调度
+    fs::write(dir.join("mix_agent_b.yml"), "cron: \"0 0 0 1 1 ?\"\ntarget: [b]\n").unwrap();
+    wait_until(&mut host, || !targets_b.lock().unwrap().is_empty());
+    assert_eq!(*targets_b.lock().unwrap(), vec![vec!["b".to_string()]]);
+    assert_eq!(runs_a.load(Ordering::SeqCst), 1);
+    drop(host);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_health_log() {
+    let log = health_log("mix_agent_a", None);
+    assert_eq!(log.category, "agent-health");
+    assert_eq!(log.level, "warn");
+    assert_eq!(log.raw_data, json!({ "scheduled": false }));
+
+    let mut stats = JobStats {
+        runs: 3,
+        missed_runs: 1,
+        last_duration: Some(Duration::from_millis(1500)),
+        ..Default::default()
+    };
+    let log = health_log("mix_agent_a", Some(&stats));
+    assert_eq!(log.level, "info");
+    assert_eq!(log.raw_data["runs"], 3);
+    assert_eq!(log.raw_data["missed_runs"], 1);
+    assert_eq!(log.raw_data["last_duration"], 1500);
+    assert_eq!(log.raw_data["next_run"], Value::Null);
+
+    stats.last_error = Some("70001:进程不存在".to_string());
+    let log = health_log("mix_agent_a", Some(&stats));
+    assert_eq!(log.level, "warn");
+    assert_eq!(log.raw_data["last_error"], "70001:进程不存在");
+}
@@ -0,0 +1,193 @@
+//! InfluxDB line protocol输出
+//!
+//! measurement为日志 `category`，tag取自 `identity` 及 `tags`，field为 `raw_data` 中的数值字段(展开规则同 `mix_metrics`)，
+//! `raw_data` 为数组时每个元素一行，以 `name`(没有时以 `index`)区分，时间戳取日志 `time`(纳秒)。
+//! `protocol` 为 `udp` 时发送至InfluxDB的udp监听，为 `http` 时提交至 `address` 配置的write接口，如 `http://127.0.0.1:8086/write?db=mix`。
+
+use crate::mix_metrics::{field_name, flatten, Sample};
+use crate::mix_sink::{Record, Sink, SinkConfig};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::io;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+///单个udp数据包的最大字节数，超出时拆分为多个数据包
+pub const MAX_DATAGRAM: usize = 1400;
+
+fn escape(value: &str, chars: &[char]) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || chars.contains(&c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+///一组标签及其下的字段
+type Row = (Vec<(String, String)>, Vec<(String, f64)>);
+
+/// 按标签分组，同一组标签的指标合并为一行，保持首次出现的顺序
+pub fn group(samples: Vec<Sample>) -> Vec<Row> {
+    let mut rows: Vec<Row> = vec![];
+    for sample in samples {
+        let field = (sample.name, sample.value);
+        match rows.iter_mut().find(|(labels, _)| *labels == sample.labels) {
+            Some((_, fields)) => fields.push(field),
+            None => rows.push((sample.labels, vec![field])),
+        }
+    }
+    rows
+}
+
+/// 将一条日志转换为line protocol，没有数值字段时返回空
+pub fn to_lines(log: &Value) -> Vec<String> {
+    let category = log.get("category").and_then(|v| v.as_str()).unwrap_or_default();
+    let measurement = escape(category, &[',', ' ']);
+    let timestamp = log.get("time").and_then(|v| v.as_i64()).unwrap_or_default() * 1_000_000;
+
+    group(flatten(log))
+        .into_iter()
+        .map(|(labels, fields)| {
+            let mut line = measurement.clone();
+            //influxdb不允许空的tag值
+            for (k, v) in labels.iter().filter(|(_, v)| !v.is_empty()) {
+                line.push_str(&format!(",{}={}", escape(k, &[',', '=', ' ']), escape(v, &[',', '=', ' '])));
+            }
+            let fields: Vec<String> = fields.iter().map(|(name, value)| format!("{}={}", escape(&field_name(category, name), &[',', '=', ' ']), value)).collect();
+            format!("{} {} {}", line, fields.join(","), timestamp)
+        })
+        .collect()
+}
+
+/// 按换行拼接，每个分组不超过 `max` 字节(单行超出时单独成组)
+pub fn pack(lines: &[String], max: usize) -> Vec<String> {
+    let mut packets: Vec<String> = vec![];
+    for line in lines.iter() {
+        match packets.last_mut() {
+            Some(packet) if packet.len() + 1 + line.len() <= max => {
+                packet.push('\n');
+                packet.push_str(line);
+            }
+            _ => packets.push(line.clone()),
+        }
+    }
+    packets
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    Http(Client, Duration),
+}
+
+pub struct InfluxSink {
+    address: String,
+    transport: Transport,
+}
+
+impl InfluxSink {
+    pub fn new(config: &SinkConfig, timeout: u64) -> Result<InfluxSink, String> {
+        if config.address.is_empty() {
+            return Err("influx输出未配置`address`".to_string());
+        }
+        let transport = match config.protocol.as_str() {
+            "udp" => Transport::Udp(UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?),
+            "http" => Transport::Http(Client::new(), Duration::from_millis(timeout)),
+            other => return Err(format!("不支持的influx协议: {}", other)),
+        };
+        Ok(InfluxSink {
+            address: config.address.clone(),
+            transport,
+        })
+    }
+}
+
+impl Sink for InfluxSink {
+    fn name(&self) -> String {
+        format!("influx({})", self.address)
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        let lines = to_lines(&record.value());
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        match &self.transport {
+            Transport::Udp(socket) => {
+                for packet in pack(&lines, MAX_DATAGRAM) {
+                    socket.send_to(packet.as_bytes(), &self.address)?;
+                }
+                Ok(())
+            }
+            Transport::Http(client, timeout) => {
+                let res = client.post(self.address.as_str()).body(lines.join("\n")).timeout(*timeout).send().map_err(io::Error::other)?;
+                if !res.status().is_success() {
+                    return Err(io::Error::other(format!("influx响应异常:{}, {}", res.status(), res.text().unwrap_or_default())));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[test]
+fn test_to_lines() {
+    let log: Value = serde_json::from_str(
+        r#"{
+            "identity": {"customer_id": "460001", "project_id": "P12342", "target_ip": ""},
+            "time": 1610697769239,
+            "tags": ["agent-desc|磁盘 使用情况"],
+            "category": "disk",
+            "raw_data": [
+                {"total_space": 100, "used_space": 40, "usage": 40.5, "file_system": "ext4", "name": "/"},
+                {"total_space": 50, "used_space": 5, "usage": 10.0, "file_system": "xfs", "name": "/data,1"}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let lines = to_lines(&log);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "disk,customer_id=460001,project_id=P12342,agent_desc=磁盘\\ 使用情况,name=/ total_space=100,usage=40.5,used_space=40 1610697769239000000");
+    assert!(lines[1].contains(",name=/data\\,1 "));
+
+    let packets = pack(&lines, lines[0].len() + lines[1].len() + 1);
+    assert_eq!(packets.len(), 1);
+    assert_eq!(pack(&lines, lines[0].len()).len(), 2);
+}
+
+#[test]
+fn test_influx_http() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut request = server.recv().unwrap();
+        let url = request.url().to_string();
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body).unwrap();
+        request.respond(tiny_http::Response::empty(204)).unwrap();
+        (url, body)
+    });
+
+    let config = SinkConfig {
+        kind: "influx".to_string(),
+        protocol: "http".to_string(),
+        address: format!("http://{}/write?db=mix", addr),
+        ..crate::mix_sink::default_sinks().remove(0)
+    };
+    let record = Record {
+        agent_name: "mix_agent_memory",
+        category: "memory",
+        level: "info",
+        time: 1610697769239,
+        body: r#"{"identity": {"customer_id": "c", "project_id": "p", "target_ip": "t"}, "time": 1610697769239, "tags": [], "category": "memory", "raw_data": {"usage": 41.5}}"#,
+    };
+    InfluxSink::new(&config, 5000).unwrap().write(&record).unwrap();
+
+    let (url, body) = handle.join().unwrap();
+    assert_eq!(url, "/write?db=mix");
+    assert_eq!(body, "memory,customer_id=c,project_id=p,target_ip=t usage=41.5 1610697769239000000");
+}
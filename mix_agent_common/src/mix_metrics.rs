@@ -0,0 +1,317 @@
+//! Prometheus指标
+//!
+//! 开启 `prometheus.enabled` 后，每条日志 `raw_data` 中的数值字段按 `mix_<category>_<字段名>` 记录为gauge，
+//! 标签取自 `identity`(customer_id、project_id、target_ip) 及 `tags`(`key|value`)，数组元素以 `name` 字段
+//! (没有时以序号 `index`)区分。探针在 `Monitor::begin` 中启动http监听，通过 `/metrics` 以Prometheus文本格式输出各类别最新一次的采集结果。
+
+use crate::mix_sink::{Record, Sink};
+use lazy_static::lazy_static;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::sync::{Mutex, Once};
+use std::thread;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct PrometheusConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    ///监听地址
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    ///按探针名称单独指定监听地址，多个探针运行在同一台服务器时需使用不同端口
+    #[serde(default)]
+    pub agent_listen: BTreeMap<String, String>,
+}
+
+fn default_listen() -> String {
+    "0.0.0.0:9464".to_string()
+}
+
+impl Default for PrometheusConfig {
+    fn default() -> Self {
+        PrometheusConfig {
+            enabled: false,
+            listen: default_listen(),
+            agent_listen: BTreeMap::new(),
+        }
+    }
+}
+
+impl PrometheusConfig {
+    pub fn listen_for(&self, agent_name: &str) -> &str {
+        self.agent_listen.get(agent_name).unwrap_or(&self.listen)
+    }
+}
+
+/// 一个指标值
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+/// 将名称转换为合法的指标名、标签名
+pub fn sanitize(name: &str) -> String {
+    let mut result: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c.to_ascii_lowercase() } else { '_' }).collect();
+    if result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    result
+}
+
+/// 取日志的公共标签：identity及tags
+pub fn log_labels(log: &Value) -> Vec<(String, String)> {
+    let mut labels = vec![];
+    if let Some(identity) = log.get("identity").and_then(|v| v.as_object()) {
+        for key in ["customer_id", "project_id", "target_ip"].iter() {
+            if let Some(v) = identity.get(*key).and_then(|v| v.as_str()) {
+                labels.push((key.to_string(), v.to_string()));
+            }
+        }
+    }
+    if let Some(tags) = log.get("tags").and_then(|v| v.as_array()) {
+        for tag in tags.iter().filter_map(|t| t.as_str()) {
+            if let Some((k, v)) = tag.split_once('|') {
+                labels.push((sanitize(k), v.to_string()));
+            }
+        }
+    }
+    labels
+}
+
+/// 展开一条日志 `raw_data` 中的数值字段
+pub fn flatten(log: &Value) -> Vec<Sample> {
+    let category = log.get("category").and_then(|v| v.as_str()).unwrap_or_default();
+    let labels = log_labels(log);
+    let mut samples = vec![];
+    if let Some(raw_data) = log.get("raw_data") {
+        collect(&format!("mix_{}", sanitize(category)), "", raw_data, &labels, &mut samples);
+    }
+    samples
+}
+
+/// 指标名去掉 `mix_<category>_` 前缀后的字段名，`raw_data` 本身为数值时为 `value`
+pub fn field_name(category: &str, name: &str) -> String {
+    let prefix = format!("mix_{}", sanitize(category));
+    match name.strip_prefix(&prefix) {
+        Some("") | None => "value".to_string(),
+        Some(field) => field.trim_start_matches('_').to_string(),
+    }
+}
+
+/// 数组元素的标签名，嵌套数组或与公共标签重名时加上数组的字段名，如`core_usage_index`，避免标签重名
+fn label_name(labels: &[(String, String)], field: &str, base: &str) -> String {
+    let taken = |name: &str| labels.iter().any(|(k, _)| k == name);
+    if !taken(base) {
+        return base.to_string();
+    }
+    let name = if field.is_empty() { base.to_string() } else { format!("{}_{}", sanitize(field), base) };
+    if !taken(&name) {
+        return name;
+    }
+    (2..).map(|n| format!("{}_{}", name, n)).find(|n| !taken(n)).expect("总能找到未使用的标签名")
+}
+
+/// `field`为`value`所在的字段名，`raw_data`本身为空
+fn collect(prefix: &str, field: &str, value: &Value, labels: &[(String, String)], samples: &mut Vec<Sample>) {
+    match value {
+        Value::Number(n) => samples.push(Sample {
+            name: prefix.to_string(),
+            labels: labels.to_vec(),
+            value: n.as_f64().unwrap_or_default(),
+        }),
+        Value::Bool(b) => samples.push(Sample {
+            name: prefix.to_string(),
+            labels: labels.to_vec(),
+            value: if *b { 1.0 } else { 0.0 },
+        }),
+        Value::Object(map) => {
+            for (k, v) in map.iter() {
+                //采集时间不作为指标
+                if k == "time" {
+                    continue;
+                }
+                collect(&format!("{}_{}", prefix, sanitize(k)), k, v, labels, samples);
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let mut item_labels = labels.to_vec();
+                match item.get("name").and_then(|v| v.as_str()) {
+                    Some(name) => item_labels.push((label_name(labels, field, "name"), name.to_string())),
+                    None => item_labels.push((label_name(labels, field, "index"), i.to_string())),
+                }
+                collect(prefix, field, item, &item_labels, samples);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// 按Prometheus文本格式输出
+pub fn render(samples: &[Sample]) -> String {
+    let mut by_name: BTreeMap<&str, Vec<&Sample>> = BTreeMap::new();
+    for sample in samples.iter() {
+        by_name.entry(sample.name.as_str()).or_default().push(sample);
+    }
+
+    let mut out = String::new();
+    for (name, samples) in by_name.iter() {
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for sample in samples.iter() {
+            let labels: Vec<String> = sample.labels.iter().map(|(k, v)| format!("{}=\"{}\"", k, escape(v))).collect();
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), sample.value);
+        }
+    }
+    out
+}
+
+lazy_static! {
+    ///按 探针名称/类别 保存最新一次的指标
+    static ref LATEST: Mutex<BTreeMap<String, Vec<Sample>>> = Mutex::new(BTreeMap::new());
+}
+static LISTENER: Once = Once::new();
+
+/// 记录一条日志的指标，替换该探针同类别上一次的指标
+pub fn record(agent_name: &str, log: &Value) {
+    let samples = flatten(log);
+    if samples.is_empty() {
+        return;
+    }
+    let category = log.get("category").and_then(|v| v.as_str()).unwrap_or_default();
+    if let Ok(mut latest) = LATEST.lock() {
+        latest.insert(format!("{}/{}", agent_name, category), samples);
+    }
+}
+
+/// 当前全部指标的文本格式
+pub fn scrape() -> String {
+    match LATEST.lock() {
+        Ok(latest) => {
+            let samples: Vec<Sample> = latest.values().flatten().cloned().collect();
+            render(&samples)
+        }
+        Err(_) => String::new(),
+    }
+}
+
+/// 启动 `/metrics` http监听，同一进程只启动一次
+pub fn serve(config: &PrometheusConfig, agent_name: &str) {
+    if !config.enabled {
+        return;
+    }
+    let listen = config.listen_for(agent_name).to_string();
+    LISTENER.call_once(move || match tiny_http::Server::http(listen.as_str()) {
+        Ok(server) => {
+            info!("prometheus指标监听: http://{}/metrics", listen);
+            respond(server);
+        }
+        Err(e) => error!("prometheus监听失败, 地址:{}, 错误:{}", listen, e),
+    });
+}
+
+/// 在新线程中响应`/metrics`请求
+fn respond(server: tiny_http::Server) {
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4; charset=utf-8"[..]).expect("合法的响应头");
+                tiny_http::Response::from_string(scrape()).with_header(header)
+            } else {
+                tiny_http::Response::from_string("not found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                error!("prometheus响应失败:{}", e);
+            }
+        }
+    });
+}
+
+/// 记录指标供 `/metrics` 输出，开启 `prometheus.enabled` 时自动加入输出目标
+pub struct PrometheusSink;
+
+impl Sink for PrometheusSink {
+    fn name(&self) -> String {
+        "prometheus".to_string()
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        self::record(record.agent_name, &record.value());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flatten_and_render() {
+    let log: Value = serde_json::from_str(
+        r#"{
+            "identity": {"customer_id": "460001", "project_id": "P12342", "target_ip": "192.168.30.11"},
+            "tags": ["agent-desc|磁盘使用情况", "data-unit|字节"],
+            "category": "disk",
+            "raw_data": [
+                {"total_space": 100, "used_space": 40, "usage": 40.0, "file_system": "ext4", "name": "/"},
+                {"total_space": 50, "used_space": 5, "usage": 10.0, "file_system": "xfs", "name": "/data"}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let samples = flatten(&log);
+    assert_eq!(samples.len(), 6);
+    let usage: Vec<&Sample> = samples.iter().filter(|s| s.name == "mix_disk_usage").collect();
+    assert_eq!(usage.len(), 2);
+    assert_eq!(usage[1].value, 10.0);
+    assert!(usage[1].labels.contains(&("name".to_string(), "/data".to_string())));
+    assert!(usage[1].labels.contains(&("data_unit".to_string(), "字节".to_string())));
+
+    let text = render(&samples);
+    assert!(text.contains("# TYPE mix_disk_usage gauge\n"));
+    assert!(text.contains("mix_disk_used_space{customer_id=\"460001\",project_id=\"P12342\",target_ip=\"192.168.30.11\",agent_desc=\"磁盘使用情况\",data_unit=\"字节\",name=\"/\"} 40\n"));
+}
+
+#[test]
+fn test_nested_array_labels() {
+    let log: Value = serde_json::from_str(
+        r#"{
+            "identity": {"customer_id": "c", "project_id": "p", "target_ip": "t"},
+            "tags": ["name|host"],
+            "category": "cpu",
+            "raw_data": {"samples": [{"usage": 10, "core_usage": [5, 15]}, {"usage": 20, "core_usage": [25, 35]}]}
+        }"#,
+    )
+    .unwrap();
+    let samples = flatten(&log);
+    let cores: Vec<&Sample> = samples.iter().filter(|s| s.name == "mix_cpu_samples_core_usage").collect();
+    assert_eq!(cores.len(), 4);
+    for sample in samples.iter() {
+        let mut names: Vec<&str> = sample.labels.iter().map(|(k, _)| k.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), sample.labels.len(), "{:?}", sample.labels);
+    }
+    assert!(render(&samples).contains("mix_cpu_samples_core_usage{customer_id=\"c\",project_id=\"p\",target_ip=\"t\",name=\"host\",index=\"1\",core_usage_index=\"0\"} 25\n"));
+}
+
+#[test]
+fn test_serve_metrics() {
+    let log: Value = serde_json::from_str(r#"{"identity": {"customer_id": "c", "project_id": "p", "target_ip": "t"}, "tags": [], "category": "memory", "raw_data": {"usage": 41.5}}"#).unwrap();
+    record("mix_agent_memory", &log);
+
+    //使用系统分配的端口，避免与其他进程冲突
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    respond(server);
+    let text = reqwest::blocking::get(format!("http://{}/metrics", addr)).unwrap().text().unwrap();
+    assert_eq!(reqwest::blocking::get(format!("http://{}/other", addr)).unwrap().status(), 404);
+    assert!(text.contains("mix_memory_usage{customer_id=\"c\",project_id=\"p\",target_ip=\"t\"} 41.5"));
+}
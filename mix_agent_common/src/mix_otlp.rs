@@ -0,0 +1,385 @@
+//! OpenTelemetry OTLP/HTTP指标输出
+//!
+//! 将日志 `raw_data` 中的数值字段(展开规则同 `mix_metrics`)转换为OTLP gauge指标，
+//! 以 `identity`、`source` 作为resource属性，按protobuf或json编码发送至 `address` 配置的OTLP/HTTP地址(如 `http://127.0.0.1:4318/v1/metrics`)。
+
+use crate::mix_metrics::flatten;
+use crate::mix_sink::{Record, Sink, SinkConfig};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Value};
+use std::io;
+use std::time::Duration;
+
+const IDENTITY_LABELS: [&str; 3] = ["customer_id", "project_id", "target_ip"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataPoint {
+    pub attributes: Vec<(String, String)>,
+    pub time_unix_nano: u64,
+    pub value: f64,
+}
+
+/// 一次导出请求，对应 `ExportMetricsServiceRequest` 中的一个resource
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsRequest {
+    pub resource: Vec<(String, String)>,
+    ///指标名称及数据点，保持首次出现的顺序
+    pub metrics: Vec<(String, Vec<DataPoint>)>,
+}
+
+fn string_at(log: &Value, path: &[&str]) -> String {
+    let mut value = log;
+    for key in path.iter() {
+        value = match value.get(key) {
+            Some(v) => v,
+            None => return String::new(),
+        };
+    }
+    value.as_str().unwrap_or_default().to_string()
+}
+
+impl MetricsRequest {
+    /// 由日志构造导出请求
+    pub fn from_log(log: &Value) -> MetricsRequest {
+        let mut resource = vec![
+            ("service.name".to_string(), string_at(log, &["source", "name"])),
+            ("service.version".to_string(), string_at(log, &["source", "version"])),
+            ("host.ip".to_string(), string_at(log, &["source", "ip"])),
+            ("telemetry.sdk.language".to_string(), string_at(log, &["source", "lang"])),
+            ("mix.source.from".to_string(), string_at(log, &["source", "from"])),
+            ("mix.env".to_string(), string_at(log, &["env"])),
+        ];
+        for key in IDENTITY_LABELS.iter() {
+            resource.push((format!("mix.{}", key), string_at(log, &["identity", key])));
+        }
+
+        let time_unix_nano = log.get("time").and_then(|v| v.as_i64()).unwrap_or_default().max(0) as u64 * 1_000_000;
+        let mut metrics: Vec<(String, Vec<DataPoint>)> = vec![];
+        for sample in flatten(log) {
+            let point = DataPoint {
+                attributes: sample.labels.into_iter().filter(|(k, _)| !IDENTITY_LABELS.contains(&k.as_str())).collect(),
+                time_unix_nano,
+                value: sample.value,
+            };
+            match metrics.iter_mut().find(|(name, _)| *name == sample.name) {
+                Some((_, points)) => points.push(point),
+                None => metrics.push((sample.name, vec![point])),
+            }
+        }
+
+        MetricsRequest { resource, metrics }
+    }
+
+    /// OTLP/JSON编码
+    pub fn to_json(&self) -> Value {
+        let attributes = |attrs: &[(String, String)]| -> Value { attrs.iter().map(|(k, v)| json!({"key": k, "value": {"stringValue": v}})).collect() };
+        let metrics: Vec<Value> = self
+            .metrics
+            .iter()
+            .map(|(name, points)| {
+                let points: Vec<Value> = points
+                    .iter()
+                    .map(|p| json!({"attributes": attributes(&p.attributes), "timeUnixNano": p.time_unix_nano.to_string(), "asDouble": p.value}))
+                    .collect();
+                json!({"name": name, "gauge": {"dataPoints": points}})
+            })
+            .collect();
+
+        json!({
+            "resourceMetrics": [{
+                "resource": {"attributes": attributes(&self.resource)},
+                "scopeMetrics": [{
+                    "scope": {"name": "mix_agent"},
+                    "metrics": metrics
+                }]
+            }]
+        })
+    }
+
+    /// OTLP/protobuf编码
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        let key_value = |w: &mut ProtoWriter, field: u32, (k, v): &(String, String)| {
+            w.message(field, |kv| {
+                kv.string(1, k);
+                kv.message(2, |any| any.string(1, v));
+            });
+        };
+
+        let mut request = ProtoWriter::default();
+        //ExportMetricsServiceRequest.resource_metrics
+        request.message(1, |rm| {
+            //ResourceMetrics.resource
+            rm.message(1, |resource| {
+                for attr in self.resource.iter() {
+                    key_value(resource, 1, attr);
+                }
+            });
+            //ResourceMetrics.scope_metrics
+            rm.message(2, |sm| {
+                sm.message(1, |scope| scope.string(1, "mix_agent"));
+                for (name, points) in self.metrics.iter() {
+                    //ScopeMetrics.metrics
+                    sm.message(2, |metric| {
+                        metric.string(1, name);
+                        //Metric.gauge
+                        metric.message(5, |gauge| {
+                            for point in points.iter() {
+                                //Gauge.data_points
+                                gauge.message(1, |dp| {
+                                    dp.fixed64(3, point.time_unix_nano);
+                                    dp.double(4, point.value);
+                                    for attr in point.attributes.iter() {
+                                        key_value(dp, 7, attr);
+                                    }
+                                });
+                            }
+                        });
+                    });
+                }
+            });
+        });
+        request.0
+    }
+}
+
+/// 最小化的protobuf写入，仅支持OTLP指标用到的类型
+#[derive(Default)]
+struct ProtoWriter(Vec<u8>);
+
+impl ProtoWriter {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.tag(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, field: u32, s: &str) {
+        self.bytes(field, s.as_bytes());
+    }
+
+    fn fixed64(&mut self, field: u32, v: u64) {
+        self.tag(field, 1);
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn double(&mut self, field: u32, v: f64) {
+        self.fixed64(field, v.to_bits());
+    }
+
+    fn message<F: FnOnce(&mut ProtoWriter)>(&mut self, field: u32, f: F) {
+        let mut inner = ProtoWriter::default();
+        f(&mut inner);
+        self.bytes(field, &inner.0);
+    }
+}
+
+pub struct OtlpSink {
+    address: String,
+    protobuf: bool,
+    timeout: Duration,
+    client: Client,
+}
+
+impl OtlpSink {
+    pub fn new(config: &SinkConfig, timeout: u64) -> Result<OtlpSink, String> {
+        if config.address.is_empty() {
+            return Err("otlp输出未配置`address`".to_string());
+        }
+        let protobuf = match config.encoding.as_str() {
+            "protobuf" => true,
+            "json" => false,
+            other => return Err(format!("不支持的otlp编码: {}", other)),
+        };
+        Ok(OtlpSink {
+            address: config.address.clone(),
+            protobuf,
+            timeout: Duration::from_millis(timeout),
+            client: Client::new(),
+        })
+    }
+}
+
+impl Sink for OtlpSink {
+    fn name(&self) -> String {
+        format!("otlp({})", self.address)
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        let request = MetricsRequest::from_log(&record.value());
+        if request.metrics.is_empty() {
+            return Ok(());
+        }
+
+        let (content_type, body) = if self.protobuf {
+            ("application/x-protobuf", request.to_protobuf())
+        } else {
+            ("application/json", request.to_json().to_string().into_bytes())
+        };
+        let res = self.client.post(self.address.as_str()).header(CONTENT_TYPE, content_type).body(body).timeout(self.timeout).send().map_err(io::Error::other)?;
+        if !res.status().is_success() {
+            return Err(io::Error::other(format!("otlp响应异常:{}", res.status())));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn sample_log() -> Value {
+    serde_json::from_str(
+        r#"{
+            "identity": {"customer_id": "460001", "project_id": "P12342", "target_ip": "192.168.30.11"},
+            "time": 1610697769239,
+            "tags": ["agent-desc|cpu使用率"],
+            "category": "cpu",
+            "raw_data": [{"time": 1610697769239, "usage": 12.5}],
+            "env": "prod",
+            "source": {"from": "agent", "name": "mix_agent_cpu", "version": "v1.0.0", "lang": "rust", "ip": "192.168.30.199"}
+        }"#,
+    )
+    .unwrap()
+}
+
+/// 测试用的protobuf解码，返回 (字段号, 值)，长度类型的值为原始字节
+#[cfg(test)]
+fn decode(mut buf: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    fn varint(buf: &mut &[u8]) -> u64 {
+        let mut v = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = buf[0];
+            *buf = &buf[1..];
+            v |= ((b & 0x7f) as u64) << shift;
+            if b < 0x80 {
+                return v;
+            }
+            shift += 7;
+        }
+    }
+
+    let mut fields = vec![];
+    while !buf.is_empty() {
+        let tag = varint(&mut buf);
+        let value = match tag & 7 {
+            0 => varint(&mut buf).to_le_bytes().to_vec(),
+            1 => {
+                let v = buf[..8].to_vec();
+                buf = &buf[8..];
+                v
+            }
+            2 => {
+                let len = varint(&mut buf) as usize;
+                let v = buf[..len].to_vec();
+                buf = &buf[len..];
+                v
+            }
+            other => panic!("unexpected wire type {}", other),
+        };
+        fields.push(((tag >> 3) as u32, value));
+    }
+    fields
+}
+
+#[test]
+fn test_otlp_json_collector() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut request = server.recv().unwrap();
+        let content_type = request.headers().iter().find(|h| h.field.equiv("Content-Type")).map(|h| h.value.to_string());
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body).unwrap();
+        request.respond(tiny_http::Response::from_string("{}")).unwrap();
+        (content_type, body)
+    });
+
+    let config = SinkConfig {
+        kind: "otlp".to_string(),
+        address: format!("http://{}/v1/metrics", addr),
+        encoding: "json".to_string(),
+        ..crate::mix_sink::default_sinks().remove(0)
+    };
+    let body = sample_log().to_string();
+    let record = Record {
+        agent_name: "mix_agent_cpu",
+        category: "cpu",
+        level: "info",
+        time: 1610697769239,
+        body: &body,
+    };
+    OtlpSink::new(&config, 5000).unwrap().write(&record).unwrap();
+
+    let (content_type, body) = handle.join().unwrap();
+    assert_eq!(content_type.unwrap(), "application/json");
+    let payload: Value = serde_json::from_str(&body).unwrap();
+    let rm = &payload["resourceMetrics"][0];
+    let attrs = rm["resource"]["attributes"].as_array().unwrap();
+    assert!(attrs.contains(&json!({"key": "service.name", "value": {"stringValue": "mix_agent_cpu"}})));
+    assert!(attrs.contains(&json!({"key": "mix.customer_id", "value": {"stringValue": "460001"}})));
+    let metric = &rm["scopeMetrics"][0]["metrics"][0];
+    assert_eq!(metric["name"], "mix_cpu_usage");
+    let point = &metric["gauge"]["dataPoints"][0];
+    assert_eq!(point["asDouble"], 12.5);
+    assert_eq!(point["timeUnixNano"], "1610697769239000000");
+    assert!(point["attributes"].as_array().unwrap().contains(&json!({"key": "index", "value": {"stringValue": "0"}})));
+}
+
+#[test]
+fn test_otlp_protobuf_collector() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut request = server.recv().unwrap();
+        let mut body = vec![];
+        request.as_reader().read_to_end(&mut body).unwrap();
+        request.respond(tiny_http::Response::from_string("")).unwrap();
+        body
+    });
+
+    let config = SinkConfig {
+        kind: "otlp".to_string(),
+        address: format!("http://{}/v1/metrics", addr),
+        encoding: "protobuf".to_string(),
+        ..crate::mix_sink::default_sinks().remove(0)
+    };
+    let body = sample_log().to_string();
+    let record = Record {
+        agent_name: "mix_agent_cpu",
+        category: "cpu",
+        level: "info",
+        time: 1610697769239,
+        body: &body,
+    };
+    OtlpSink::new(&config, 5000).unwrap().write(&record).unwrap();
+
+    let body = handle.join().unwrap();
+    let request = decode(&body);
+    assert_eq!(request.len(), 1);
+    let resource_metrics = decode(&request[0].1);
+    let resource = decode(&resource_metrics[0].1);
+    let first_attr = decode(&resource[0].1);
+    assert_eq!(first_attr[0].1, b"service.name".to_vec());
+    assert_eq!(decode(&first_attr[1].1)[0].1, b"mix_agent_cpu".to_vec());
+
+    let scope_metrics = decode(&resource_metrics[1].1);
+    let metric = decode(&scope_metrics[1].1);
+    assert_eq!(metric[0].1, b"mix_cpu_usage".to_vec());
+    let gauge = decode(&metric[1].1);
+    let point = decode(&gauge[0].1);
+    assert_eq!(point[0].0, 3);
+    assert_eq!(u64::from_le_bytes(point[0].1[..].try_into().unwrap()), 1610697769239000000);
+    assert_eq!(point[1].0, 4);
+    assert_eq!(f64::from_le_bytes(point[1].1[..].try_into().unwrap()), 12.5);
+}
@@ -0,0 +1,187 @@
+//! 命令行及环境变量覆盖配置
+//!
+//! 优先级：命令行 > 环境变量 > 远程配置(见 `mix_remote`) > 配置文件 > 默认值。
+//!
+//! * 目录：`--config-dir`/`MIX_CONFIG_DIR` 默认为程序目录/config，`--log-dir`/`MIX_LOG_DIR` 默认为程序目录/log
+//! * 密钥文件：`--key-file`/`MIX_KEY_FILE` 默认为配置目录/mix.key，见 `mix_secret`
+//! * 全局配置：`MIX_<配置项>`，如 `MIX_CUSTOMER_ID` 对应 `customer-id`，`MIX_ENDPOINT` 对应 `mix-endpoint`
+//! * 探针配置：`<探针名>__<配置项>`，如 `MIX_AGENT_CPU__CRON` 对应mix_agent_cpu.yml中的 `cron`
+//! * 嵌套配置项以 `__` 分隔，如 `MIX_RETRY__MAX_ATTEMPTS`；命令行以 `--set <变量名>=<值>` 覆盖，变量名与环境变量相同
+
+use crate::mix_config::get_current_dir;
+use config::Config;
+use lazy_static::lazy_static;
+use std::env;
+use std::path::{Path, PathBuf};
+
+///不属于全局配置项的环境变量
+const RESERVED: [&str; 3] = ["MIX_CONFIG_DIR", "MIX_LOG_DIR", "MIX_KEY_FILE"];
+
+///全局配置中以 `mix-` 开头的配置项，环境变量省略前缀
+const GLOBAL_ALIASES: [(&str, &str); 2] = [("endpoint", "mix-endpoint"), ("endpoint-key", "mix-endpoint-key")];
+
+///带参数值的命令行选项
+pub const OPTION_NAMES: [&str; 4] = ["--config-dir", "--log-dir", "--key-file", "--set"];
+
+/// 命令行参数
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub config_dir: Option<PathBuf>,
+    pub log_dir: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    ///`--set` 指定的配置项，按出现顺序
+    pub sets: Vec<(String, String)>,
+}
+
+impl Options {
+    /// 解析 `--name value` 或 `--name=value` 形式的参数，忽略其他参数
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Options {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((n, v)) if n.starts_with("--") => (n.to_string(), Some(v.to_string())),
+                _ => (arg, None),
+            };
+            if !OPTION_NAMES.contains(&name.as_str()) {
+                continue;
+            }
+            let value = match inline.or_else(|| args.next()) {
+                Some(v) => v,
+                None => break,
+            };
+            match name.as_str() {
+                "--config-dir" => options.config_dir = Some(PathBuf::from(value)),
+                "--log-dir" => options.log_dir = Some(PathBuf::from(value)),
+                "--key-file" => options.key_file = Some(PathBuf::from(value)),
+                _ => {
+                    if let Some((k, v)) = value.split_once('=') {
+                        options.sets.push((k.to_string(), v.to_string()));
+                    }
+                }
+            }
+        }
+        options
+    }
+}
+
+lazy_static! {
+    static ref OPTIONS: Options = Options::parse(env::args().skip(1));
+}
+
+fn dir(cli: &Option<PathBuf>, var: &str, default: &str) -> PathBuf {
+    cli.clone().or_else(|| env::var_os(var).map(PathBuf::from)).unwrap_or_else(|| Path::new(&get_current_dir()).join(default))
+}
+
+/// 配置目录
+pub fn config_dir() -> PathBuf {
+    dir(&OPTIONS.config_dir, "MIX_CONFIG_DIR", "config")
+}
+
+/// 日志目录
+pub fn log_dir() -> PathBuf {
+    dir(&OPTIONS.log_dir, "MIX_LOG_DIR", "log")
+}
+
+/// 密钥文件
+pub fn key_file() -> PathBuf {
+    OPTIONS.key_file.clone().or_else(|| env::var_os("MIX_KEY_FILE").map(PathBuf::from)).unwrap_or_else(|| config_dir().join("mix.key"))
+}
+
+/// 变量名对应的配置项，探针配置同时尝试 `_` 及 `-` 两种写法
+fn keys_of(config_name: &str, var: &str) -> Vec<String> {
+    let upper = var.to_uppercase();
+    let (rest, global) = if config_name == "global" {
+        match upper.strip_prefix("MIX_") {
+            Some(rest) if !upper.starts_with("MIX_AGENT_") && !RESERVED.contains(&upper.as_str()) => (rest.to_string(), true),
+            _ => return vec![],
+        }
+    } else {
+        match upper.strip_prefix(&format!("{}__", config_name.to_uppercase())) {
+            Some(rest) => (rest.to_string(), false),
+            None => return vec![],
+        }
+    };
+    if rest.is_empty() {
+        return vec![];
+    }
+
+    let snake: Vec<String> = rest.split("__").map(|s| s.to_lowercase()).collect();
+    let kebab: Vec<String> = snake.iter().map(|s| s.replace('_', "-")).collect();
+    if global {
+        let mut kebab = kebab;
+        if let Some((_, key)) = GLOBAL_ALIASES.iter().find(|(alias, _)| *alias == kebab[0]) {
+            kebab[0] = key.to_string();
+        }
+        return vec![kebab.join(".")];
+    }
+    let mut keys = vec![snake.join("."), kebab.join(".")];
+    keys.dedup();
+    keys
+}
+
+/// 生效的覆盖项(配置项, 值)，按优先级从低到高排列
+pub fn overrides(config_name: &str, vars: &[(String, String)], sets: &[(String, String)]) -> Vec<(String, String)> {
+    vars.iter().chain(sets.iter()).flat_map(|(var, value)| keys_of(config_name, var).into_iter().map(move |k| (k, value.clone()))).collect()
+}
+
+/// 当前进程的覆盖项
+pub fn current(config_name: &str) -> Vec<(String, String)> {
+    let vars: Vec<(String, String)> = env::vars().collect();
+    overrides(config_name, &vars, &OPTIONS.sets)
+}
+
+/// 将覆盖项写入配置
+pub fn apply(c: &mut Config, overrides: &[(String, String)]) -> Result<(), config::ConfigError> {
+    for (key, value) in overrides.iter() {
+        c.set(key, value.as_str())?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_options() {
+    let args = vec!["validate-config", "--config-dir", "/etc/mix", "--log-dir=/var/log/mix", "--set", "MIX_AGENT_CPU__CRON=*/5 * * * * ?", "--format", "json"];
+    let options = Options::parse(args.into_iter().map(String::from));
+    assert_eq!(options.config_dir, Some(PathBuf::from("/etc/mix")));
+    assert_eq!(options.log_dir, Some(PathBuf::from("/var/log/mix")));
+    assert_eq!(options.sets, vec![("MIX_AGENT_CPU__CRON".to_string(), "*/5 * * * * ?".to_string())]);
+}
+
+#[test]
+fn test_overrides() {
+    let vars: Vec<(String, String)> = vec![("MIX_CUSTOMER_ID", "env"), ("MIX_ENDPOINT", "http://env"), ("MIX_RETRY__MAX_ATTEMPTS", "5"), ("MIX_AGENT_CPU__CRON", "*/5 * * * * ?"), ("MIX_AGENT_API__TARGET_SOURCE__IP", "10.0.0.1"), ("MIX_LOG_DIR", "/tmp"), ("PATH", "/bin")]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let sets = vec![("MIX_CUSTOMER_ID".to_string(), "cli".to_string())];
+
+    let global = overrides("global", &vars, &sets);
+    let expect: Vec<(String, String)> = vec![("customer-id", "env"), ("mix-endpoint", "http://env"), ("retry.max-attempts", "5"), ("customer-id", "cli")]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    assert_eq!(global, expect);
+
+    assert_eq!(overrides("mix_agent_cpu", &vars, &[]), vec![("cron".to_string(), "*/5 * * * * ?".to_string())]);
+    let api: Vec<String> = overrides("mix_agent_api", &vars, &[]).into_iter().map(|(k, _)| k).collect();
+    assert_eq!(api, vec!["target_source.ip", "target-source.ip"]);
+}
+
+#[test]
+fn test_apply() {
+    let yml = "customer-id: file\nproject-id: p\nmix-endpoint: http://file\nmix-endpoint-key: k\ntimeout: 1000\n";
+    let mut c = Config::default();
+    c.merge(config::File::from_str(yml, config::FileFormat::Yaml)).unwrap();
+    let vars = vec![("MIX_CUSTOMER_ID".to_string(), "env".to_string()), ("MIX_TIMEOUT".to_string(), "3000".to_string()), ("MIX_RETRY__MAX_ATTEMPTS".to_string(), "5".to_string()), ("MIX_AGENTS".to_string(), "cpu, memory".to_string())];
+    let sets = vec![("MIX_TIMEOUT".to_string(), "9000".to_string())];
+    apply(&mut c, &overrides("global", &vars, &sets)).unwrap();
+
+    let global = c.try_into::<crate::GlobalConfig>().unwrap();
+    assert_eq!(global.customer_id, "env");
+    assert_eq!(global.mix_endpoint, "http://file");
+    assert_eq!(global.timeout, 9000);
+    assert_eq!(global.retry.max_attempts, 5);
+    assert_eq!(global.env, "dev");
+    assert_eq!(global.agents, vec!["cpu", "memory"]);
+}
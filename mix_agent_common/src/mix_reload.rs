@@ -0,0 +1,257 @@
+//! 配置热加载
+//!
+//! `Monitor::begin_with_config` 监听探针配置文件及 `global.yml`，文件变化后重新解析、校验，
+//! 校验通过则在两次调度之间替换配置及调度计划，否则继续使用原配置，两种情况均提交一条 `agent` 类别的日志。
+//! 优先使用inotify等系统通知，无法监听时改为定时比较文件的修改时间及大小。
+
+use crate::mix_config::{read, AgentConfig, ConfigError, EVERY};
+use crate::GlobalConfig;
+use crate::mix_scheduler::{parse_timezone, Trigger};
+use log::warn;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, SystemTime};
+
+///系统通知的合并间隔，编辑器保存时通常会触发多个事件
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    fs::metadata(path).ok().map(|m| (m.modified().unwrap_or(SystemTime::UNIX_EPOCH), m.len()))
+}
+
+/// 监听一组配置文件的变化
+pub struct Watcher {
+    files: Vec<PathBuf>,
+    stamps: Vec<Stamp>,
+    events: Option<(RecommendedWatcher, Receiver<DebouncedEvent>)>,
+}
+
+impl Watcher {
+    /// 优先使用系统通知，失败时改为轮询
+    pub fn new(files: Vec<PathBuf>) -> Watcher {
+        let mut watcher = Watcher::polling(files);
+        match watcher.notify() {
+            Ok(events) => watcher.events = Some(events),
+            Err(e) => warn!("无法监听配置目录，改为轮询, 错误:{}", e),
+        }
+        watcher
+    }
+
+    /// 仅轮询文件的修改时间及大小
+    pub fn polling(files: Vec<PathBuf>) -> Watcher {
+        let stamps = files.iter().map(|f| stamp(f)).collect();
+        Watcher { files, stamps, events: None }
+    }
+
+    pub fn is_polling(&self) -> bool {
+        self.events.is_none()
+    }
+
+    fn notify(&self) -> notify::Result<(RecommendedWatcher, Receiver<DebouncedEvent>)> {
+        let (tx, rx) = channel();
+        let mut watcher: RecommendedWatcher = notify::Watcher::new(tx, DEBOUNCE)?;
+        //监听所在目录而不是文件本身，编辑器保存时可能先删除再创建文件
+        let mut dirs: Vec<&Path> = self.files.iter().filter_map(|f| f.parent()).collect();
+        dirs.sort_unstable();
+        dirs.dedup();
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        Ok((watcher, rx))
+    }
+
+    fn index_of(&self, path: &Path) -> Option<usize> {
+        //系统通知的路径可能已解析符号链接，按文件名比较
+        self.files.iter().position(|f| f.file_name() == path.file_name())
+    }
+
+    /// 自上次检查后发生变化的文件
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![false; self.files.len()];
+        match &self.events {
+            Some((_, rx)) => {
+                for event in rx.try_iter() {
+                    let path = match event {
+                        DebouncedEvent::Create(p) | DebouncedEvent::Write(p) | DebouncedEvent::Chmod(p) | DebouncedEvent::Remove(p) | DebouncedEvent::Rename(_, p) => p,
+                        _ => continue,
+                    };
+                    if let Some(i) = self.index_of(&path) {
+                        changed[i] = true;
+                    }
+                }
+            }
+            None => {
+                for (i, file) in self.files.iter().enumerate() {
+                    let current = stamp(file);
+                    if current != self.stamps[i] {
+                        self.stamps[i] = current;
+                        changed[i] = true;
+                    }
+                }
+            }
+        }
+        self.files.iter().zip(changed).filter(|(_, c)| *c).map(|(f, _)| f.clone()).collect()
+    }
+}
+
+/// 校验探针配置，通过时返回调度计划
+pub fn check<C: AgentConfig>(config: &C, file: &str) -> Result<Trigger, ConfigError> {
+    let cron = config.cron();
+    let mut schedule = match cron.trim().strip_prefix(EVERY) {
+        Some(every) => Trigger::from_str(cron).map_err(|e| ConfigError::field(file, "every", &format!("无效的执行间隔`{}`: {}", every.trim(), e)))?,
+        None => Trigger::from_str(cron).map_err(|e| ConfigError::field(file, "cron", &format!("无效的cron表达式: {}", e)))?,
+    };
+    let timezone = config.timezone().trim();
+    if !timezone.is_empty() {
+        let timezone = parse_timezone(timezone).map_err(|e| ConfigError::field(file, "timezone", &format!("无效的时区: {}", e)))?;
+        //`cron`中的`CRON_TZ=`优先
+        schedule.timezone = schedule.timezone.or(Some(timezone));
+    }
+    config.validate().map_err(|(path, reason)| ConfigError::field(file, &path, &reason))?;
+    Ok(schedule)
+}
+
+fn read_existing<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let file = path.to_string_lossy();
+    if !path.exists() && !crate::mix_remote::cache_path(path).exists() {
+        return Err(ConfigError::field(&file, "", "配置文件不存在"));
+    }
+    read::<T>(&file)
+}
+
+/// 重新读取探针配置，文件不存在或校验失败时返回错误
+pub fn reload<C: AgentConfig>(path: &Path) -> Result<(C, Trigger), ConfigError> {
+    let config = read_existing::<C>(path)?;
+    let schedule = check(&config, &path.to_string_lossy())?;
+    Ok((config, schedule))
+}
+
+/// 重新读取全局配置
+pub fn reload_global(path: &Path) -> Result<GlobalConfig, ConfigError> {
+    let config = read_existing::<GlobalConfig>(path)?;
+    config.validate(&path.to_string_lossy())?;
+    Ok(config)
+}
+
+#[cfg(test)]
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct TestConfig {
+    pub cron: String,
+    #[serde(default)]
+    pub target: Vec<String>,
+    #[serde(default)]
+    pub timezone: String,
+}
+
+#[cfg(test)]
+impl crate::mix_config::MixConfig for TestConfig {
+    fn new() -> Self {
+        TestConfig {
+            cron: "*/5 * * * * ?".to_string(),
+            target: vec![],
+            timezone: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl AgentConfig for TestConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+
+    fn validate(&self) -> Result<(), (String, String)> {
+        if let Some(i) = self.target.iter().position(|t| t.is_empty()) {
+            return Err((format!("target[{}]", i), "不能为空".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_reload_validate() {
+    let dir = std::env::temp_dir().join(format!("mix_reload_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("mix_agent_test.yml");
+
+    assert_eq!(reload::<TestConfig>(&path).unwrap_err().reason, "配置文件不存在");
+
+    fs::write(&path, "cron: \"*/10 * * * * ?\"\ntarget: [nginx]\n").unwrap();
+    let (config, _) = reload::<TestConfig>(&path).unwrap();
+    assert_eq!(config.target, vec!["nginx"]);
+
+    fs::write(&path, "cron: \"every 10 seconds\"\n").unwrap();
+    let e = reload::<TestConfig>(&path).unwrap_err();
+    assert_eq!(e.path, "cron");
+    assert!(e.reason.starts_with("无效的cron表达式"));
+
+    fs::write(&path, "cron: \"*/10 * * * * ?\"\ntarget: [\"\"]\n").unwrap();
+    let e = reload::<TestConfig>(&path).unwrap_err();
+    assert_eq!((e.path.as_str(), e.reason.as_str()), ("target[0]", "不能为空"));
+
+    //`every`优先于`cron`
+    fs::write(&path, "cron: \"*/10 * * * * ?\"\nevery: 1h30m\n").unwrap();
+    let (config, trigger) = reload::<TestConfig>(&path).unwrap();
+    assert_eq!(config.cron, "@every 1h30m");
+    assert!(matches!(trigger.repeat, crate::mix_scheduler::Repeat::Every(d) if d == Duration::from_secs(5400)));
+
+    fs::write(&path, "every: 30\n").unwrap();
+    let e = reload::<TestConfig>(&path).unwrap_err();
+    assert_eq!(e.path, "every");
+    assert!(e.reason.starts_with("无效的执行间隔`30`"));
+
+    fs::write(&path, "cron: \"0 0 2 * * ?\"\ntimezone: Asia/Shanghai\n").unwrap();
+    let (_, trigger) = reload::<TestConfig>(&path).unwrap();
+    assert_eq!(trigger.timezone, Some(chrono_tz::Asia::Shanghai));
+
+    fs::write(&path, "cron: \"CRON_TZ=UTC 0 0 2 * * ?\"\ntimezone: Asia/Shanghai\n").unwrap();
+    let (_, trigger) = reload::<TestConfig>(&path).unwrap();
+    assert_eq!(trigger.timezone, Some(chrono_tz::UTC));
+
+    fs::write(&path, "cron: \"0 0 2 * * ?\"\ntimezone: Beijing\n").unwrap();
+    let e = reload::<TestConfig>(&path).unwrap_err();
+    assert_eq!(e.path, "timezone");
+    assert!(e.reason.starts_with("无效的时区"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_watcher() {
+    let dir = std::env::temp_dir().join(format!("mix_watch_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let agent = dir.join("mix_agent_test.yml");
+    let global = dir.join("global.yml");
+    fs::write(&agent, "cron: \"*/5 * * * * ?\"\n").unwrap();
+
+    let mut polling = Watcher::polling(vec![global.clone(), agent.clone()]);
+    let mut notify = Watcher::new(vec![global.clone(), agent.clone()]);
+    assert!(polling.changed().is_empty());
+
+    fs::write(&agent, "cron: \"*/10 * * * * ?\"\n").unwrap();
+    assert_eq!(polling.changed(), vec![agent.clone()]);
+    assert!(polling.changed().is_empty());
+
+    if !notify.is_polling() {
+        let mut changed = vec![];
+        for _ in 0..50 {
+            changed = notify.changed();
+            if !changed.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(changed, vec![agent.clone()]);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,357 @@
+//! 远程配置
+//!
+//! 开启 `remote.enabled` 后，探针每隔 `remote.interval` 秒按客户编号、项目编号、主机IP及探针名称向服务端拉取探针配置，
+//! 校验通过后缓存至 配置目录/remote/<探针名称>.json。加载配置时缓存合并在本地配置文件之上(命令行及环境变量仍优先)，
+//! 随热加载生效，生效的版本随日志的 `source.config_version` 提交。
+//! 服务端返回304或版本未变化时不更新，拉取失败时继续使用缓存。
+//! 运行期间的拉取在后台线程中进行，结果在下一次调度时生效。
+
+use crate::mix_config::{read_remote, AgentConfig, ConfigError};
+use crate::mix_reload::check;
+use crate::mix_sign::signed_post;
+use crate::{get_local_ip, GlobalConfig, HTTP_CLIENT, Identity};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct RemoteConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    ///拉取间隔，秒
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    ///拉取地址，相对于`mix-endpoint`
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+fn default_path() -> String {
+    "/mix/api/v1/agent/config".to_string()
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        RemoteConfig {
+            enabled: false,
+            interval: default_interval(),
+            path: default_path(),
+        }
+    }
+}
+
+/// 服务端下发并缓存的探针配置
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cache {
+    pub version: String,
+    ///与探针配置文件结构相同
+    pub config: Value,
+}
+
+/// 缓存路径：探针配置文件所在目录/remote/<探针名称>.json
+pub fn cache_path(agent_path: &Path) -> PathBuf {
+    let name = agent_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    agent_path.with_file_name("remote").join(format!("{}.json", name))
+}
+
+impl Cache {
+    pub fn load(agent_path: &Path) -> Option<Cache> {
+        let path = cache_path(agent_path);
+        let content = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str(&content) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                warn!("远程配置缓存无效`{}`, 错误:{}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// 先写入临时文件再替换，避免读取到写了一半的缓存
+    pub fn save(&self, agent_path: &Path) -> io::Result<()> {
+        let path = cache_path(agent_path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    identity: Identity,
+    agent_name: &'a str,
+    ///当前缓存的版本，服务端可据此返回304
+    version: &'a str,
+}
+
+/// 拉取远程配置，无更新时返回None
+pub fn fetch(global_config: &GlobalConfig, agent_name: &str, version: &str) -> Result<Option<Cache>, String> {
+    let request = Request {
+        identity: Identity {
+            customer_id: global_config.customer_id.clone(),
+            project_id: global_config.project_id.clone(),
+            target_ip: get_local_ip(),
+        },
+        agent_name,
+        version,
+    };
+    let body = serde_json::to_vec(&request).map_err(|e| e.to_string())?;
+    let url = format!("{}{}", global_config.mix_endpoint, global_config.remote.path);
+    let timeout = Duration::from_millis(global_config.timeout);
+
+    let res = signed_post(&HTTP_CLIENT, &url, &body, &global_config.mix_endpoint_key).timeout(timeout).send().map_err(|e| e.to_string())?;
+    if res.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !res.status().is_success() {
+        return Err(format!("状态码:{}", res.status()));
+    }
+    let cache = res.json::<Cache>().map_err(|e| e.to_string())?;
+    if cache.version == version {
+        return Ok(None);
+    }
+    Ok(Some(cache))
+}
+
+lazy_static! {
+    static ref VERSIONS: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+/// 探针当前生效的远程配置版本，未使用远程配置时为空
+pub fn config_version(agent_name: &str) -> String {
+    VERSIONS.read().ok().and_then(|v| v.get(agent_name).cloned()).unwrap_or_default()
+}
+
+/// 探针配置加载成功后记录缓存的版本
+pub fn applied(agent_name: &str, agent_path: &Path) {
+    let version = Cache::load(agent_path).map(|c| c.version).unwrap_or_default();
+    if let Ok(mut versions) = VERSIONS.write() {
+        versions.insert(agent_name.to_string(), version);
+    }
+}
+
+/// 按`remote.interval`定时拉取
+pub struct Poller {
+    agent_name: String,
+    agent_path: PathBuf,
+    next: Instant,
+    ///后台拉取的结果
+    pending: Option<Receiver<Result<bool, ConfigError>>>,
+}
+
+impl Poller {
+    pub fn new(agent_name: &str, agent_path: &Path) -> Poller {
+        Poller {
+            agent_name: agent_name.to_string(),
+            agent_path: agent_path.to_path_buf(),
+            next: Instant::now(),
+            pending: None,
+        }
+    }
+
+    /// 到达拉取时间时拉取，远程配置有更新且校验通过时写入缓存并返回true；
+    /// 拉取失败时仅记录警告，远程配置无效时返回错误且不写入缓存
+    pub fn poll<C: AgentConfig>(&mut self, global_config: &GlobalConfig) -> Result<bool, ConfigError> {
+        if !self.due(global_config) {
+            return Ok(false);
+        }
+        pull::<C>(global_config, &self.agent_name, &self.agent_path)
+    }
+
+    /// 同`poll`，但在后台线程中拉取，不阻塞调度线程；本次调用返回上次后台拉取的结果
+    pub fn poll_async<C: AgentConfig + 'static>(&mut self, global_config: &GlobalConfig) -> Result<bool, ConfigError> {
+        if let Some(pending) = &self.pending {
+            return match pending.try_recv() {
+                Ok(result) => {
+                    self.pending = None;
+                    result
+                }
+                Err(TryRecvError::Empty) => Ok(false),
+                Err(TryRecvError::Disconnected) => {
+                    self.pending = None;
+                    Ok(false)
+                }
+            };
+        }
+        if !self.due(global_config) {
+            return Ok(false);
+        }
+
+        let (sender, receiver) = channel();
+        let global_config = global_config.clone();
+        let agent_name = self.agent_name.clone();
+        let agent_path = self.agent_path.clone();
+        thread::spawn(move || {
+            let _ = sender.send(pull::<C>(&global_config, &agent_name, &agent_path));
+        });
+        self.pending = Some(receiver);
+        Ok(false)
+    }
+
+    fn due(&mut self, global_config: &GlobalConfig) -> bool {
+        let remote = &global_config.remote;
+        if !remote.enabled || Instant::now() < self.next {
+            return false;
+        }
+        self.next = Instant::now() + Duration::from_secs(remote.interval);
+        true
+    }
+}
+
+/// 拉取一次远程配置，见`Poller::poll`
+fn pull<C: AgentConfig>(global_config: &GlobalConfig, agent_name: &str, agent_path: &Path) -> Result<bool, ConfigError> {
+    let version = Cache::load(agent_path).map(|c| c.version).unwrap_or_default();
+    let cache = match fetch(global_config, agent_name, &version) {
+        Ok(Some(cache)) => cache,
+        Ok(None) => return Ok(false),
+        Err(e) => {
+            warn!("拉取远程配置失败, 继续使用缓存, 错误:{}", e);
+            return Ok(false);
+        }
+    };
+
+    let file = format!("{}@{}", cache_path(agent_path).display(), cache.version);
+    let config = read_remote::<C>(&agent_path.to_string_lossy(), &cache.config).map_err(|e| ConfigError { file: file.clone(), ..e })?;
+    check(&config, &file)?;
+    cache.save(agent_path).map_err(|e| ConfigError::field(&file, "", &format!("远程配置缓存失败: {}", e)))?;
+    info!("远程配置已更新, 版本:{}", cache.version);
+    Ok(true)
+}
+
+#[cfg(test)]
+fn serve(responses: Vec<(u16, &'static str)>) -> (String, std::thread::JoinHandle<Vec<Value>>) {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut requests = vec![];
+        for (status, body) in responses {
+            let mut request = server.recv().unwrap();
+            let mut content = String::new();
+            request.as_reader().read_to_string(&mut content).unwrap();
+            requests.push(serde_json::from_str(&content).unwrap());
+            request.respond(tiny_http::Response::from_string(body).with_status_code(status)).unwrap();
+        }
+        requests
+    });
+    (format!("http://{}", addr), handle)
+}
+
+#[test]
+fn test_remote_poll() {
+    use crate::mix_reload::TestConfig;
+
+    let (endpoint, handle) = serve(vec![
+        (200, r#"{"version":"v1","config":{"target":["nginx","redis"]}}"#),
+        (304, ""),
+        (200, r#"{"version":"v2","config":{"cron":"every 10 seconds"}}"#),
+        (500, ""),
+    ]);
+    let global_config = GlobalConfig {
+        customer_id: "c".to_string(),
+        project_id: "p".to_string(),
+        mix_endpoint: endpoint,
+        remote: RemoteConfig {
+            enabled: true,
+            interval: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let dir = std::env::temp_dir().join(format!("mix_remote_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let agent_path = dir.join("mix_agent_test.yml");
+    fs::write(&agent_path, "cron: \"*/10 * * * * ?\"\ntarget: [mysql]\n").unwrap();
+    let mut poller = Poller::new("mix_agent_test", &agent_path);
+
+    //拉取到新版本，合并在本地配置之上
+    assert!(poller.poll::<TestConfig>(&global_config).unwrap());
+    let config = crate::mix_config::read::<TestConfig>(&agent_path.to_string_lossy()).unwrap();
+    assert_eq!(config.target, vec!["nginx", "redis"]);
+    assert_eq!(config.cron, "*/10 * * * * ?");
+    applied("mix_agent_test", &agent_path);
+    assert_eq!(config_version("mix_agent_test"), "v1");
+
+    //未变化
+    assert!(!poller.poll::<TestConfig>(&global_config).unwrap());
+
+    //无效的远程配置不写入缓存
+    let e = poller.poll::<TestConfig>(&global_config).unwrap_err();
+    assert_eq!(e.path, "cron");
+    assert!(e.file.ends_with("mix_agent_test.json@v2"));
+    assert_eq!(Cache::load(&agent_path).unwrap().version, "v1");
+
+    //服务端故障时继续使用缓存
+    assert!(!poller.poll::<TestConfig>(&global_config).unwrap());
+    assert_eq!(Cache::load(&agent_path).unwrap().version, "v1");
+
+    let requests = handle.join().unwrap();
+    assert_eq!(requests[0]["agent_name"], "mix_agent_test");
+    assert_eq!(requests[0]["identity"]["customer_id"], "c");
+    assert_eq!(requests[0]["version"], "");
+    assert_eq!(requests[1]["version"], "v1");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_remote_poll_async() {
+    use crate::mix_reload::TestConfig;
+
+    let (endpoint, handle) = serve(vec![(200, r#"{"version":"v1","config":{"target":["nginx"]}}"#)]);
+    let global_config = GlobalConfig {
+        customer_id: "c".to_string(),
+        project_id: "p".to_string(),
+        mix_endpoint: endpoint,
+        remote: RemoteConfig {
+            enabled: true,
+            interval: 3600,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let dir = std::env::temp_dir().join(format!("mix_remote_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let agent_path = dir.join("mix_agent_test.yml");
+    fs::write(&agent_path, "cron: \"*/10 * * * * ?\"\ntarget: [mysql]\n").unwrap();
+    let mut poller = Poller::new("mix_agent_test", &agent_path);
+
+    //首次调用仅发起后台拉取，结果在后续调用中返回
+    assert!(!poller.poll_async::<TestConfig>(&global_config).unwrap());
+    let mut updated = false;
+    for _ in 0..100 {
+        if poller.poll_async::<TestConfig>(&global_config).unwrap() {
+            updated = true;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(updated);
+    assert_eq!(Cache::load(&agent_path).unwrap().version, "v1");
+
+    //未到拉取时间
+    assert!(!poller.poll_async::<TestConfig>(&global_config).unwrap());
+    assert!(poller.pending.is_none());
+
+    handle.join().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
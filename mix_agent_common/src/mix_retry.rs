@@ -0,0 +1,198 @@
+//! 日志提交重试策略
+//!
+//! 网络错误、超时、5xx及 `retry.retryable-status` 中的状态码(如408、429)视为临时故障，按指数退避(含随机抖动)重试；
+//! 其余非成功响应(如4xx数据被拒绝)视为永久失败，不再重试。
+
+use log::{error, info, warn};
+use rand::Rng;
+use reqwest::blocking::Response;
+use reqwest::{Error, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetryConfig {
+    ///最大尝试次数(含首次提交)
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    ///首次重试前的等待时间，毫秒
+    #[serde(default = "default_base_delay")]
+    pub base_delay: u64,
+    ///单次等待时间上限，毫秒
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+    ///是否在等待时间上增加随机抖动，避免所有探针同时重试
+    #[serde(default = "default_jitter")]
+    pub jitter: bool,
+    ///5xx以外可重试的响应状态码
+    #[serde(default = "default_retryable_status")]
+    pub retryable_status: Vec<u16>,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_base_delay() -> u64 {
+    500
+}
+
+fn default_max_delay() -> u64 {
+    10000
+}
+
+fn default_jitter() -> bool {
+    true
+}
+
+fn default_retryable_status() -> Vec<u16> {
+    vec![408, 429]
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: default_max_attempts(),
+            base_delay: default_base_delay(),
+            max_delay: default_max_delay(),
+            jitter: default_jitter(),
+            retryable_status: default_retryable_status(),
+        }
+    }
+}
+
+/// 一次提交的结果
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    /// 已送达
+    Delivered,
+    /// 被端点拒绝，重试无意义
+    Rejected,
+    /// 端点暂时不可用，可稍后重试
+    Unavailable,
+}
+
+impl RetryConfig {
+    /// 第`attempt`次重试(从1开始)前的等待时间
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self.base_delay.saturating_mul(1u64 << attempt.saturating_sub(1).min(32));
+        let delay = exp.min(self.max_delay);
+        if self.jitter && delay > 0 {
+            //保留一半等待时间，另一半随机
+            let half = delay / 2;
+            return Duration::from_millis(half + rand::thread_rng().gen_range(0..=delay - half));
+        }
+        Duration::from_millis(delay)
+    }
+
+    /// 执行`send`，临时故障时按退避策略重试，直至成功、永久失败或达到最大尝试次数
+    pub fn run<F: FnMut() -> Delivery>(&self, category: &str, mut send: F) -> Delivery {
+        let mut attempt = 1;
+        loop {
+            let delivery = send();
+            if delivery != Delivery::Unavailable || attempt >= self.max_attempts {
+                return delivery;
+            }
+
+            let delay = self.delay(attempt);
+            info!("日志提交将在{}ms后重试, 类型:{}, 第{}次", delay.as_millis(), category, attempt);
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// 5xx均为服务端临时故障，4xx中仅`retryable-status`列出的可重试
+    pub fn is_retryable(&self, status: StatusCode) -> bool {
+        status.is_server_error() || self.retryable_status.contains(&status.as_u16())
+    }
+
+    /// 根据响应区分送达、临时故障与永久失败
+    pub fn classify(&self, category: &str, res: Result<Response, Error>) -> Delivery {
+        match res {
+            Ok(res) => {
+                let status = res.status();
+                if status.is_success() {
+                    info!("日志提交成功, 类型:{}, 响应:{}", category, res.text().unwrap_or_default());
+                    Delivery::Delivered
+                } else if self.is_retryable(status) {
+                    warn!("日志提交异常, 类型:{}, 状态:{}, 响应:{}", category, status, res.text().unwrap_or_default());
+                    Delivery::Unavailable
+                } else {
+                    error!("日志被拒绝, 类型:{}, 状态:{}, 响应:{}", category, status, res.text().unwrap_or_default());
+                    Delivery::Rejected
+                }
+            }
+            Err(e) => {
+                error!("日志提交失败, 类型:{}, 错误:{}", category, e);
+                Delivery::Unavailable
+            }
+        }
+    }
+}
+
+#[test]
+fn test_retry_delay() {
+    let config = RetryConfig {
+        base_delay: 100,
+        max_delay: 1000,
+        jitter: false,
+        ..Default::default()
+    };
+    assert_eq!(config.delay(1), Duration::from_millis(100));
+    assert_eq!(config.delay(2), Duration::from_millis(200));
+    assert_eq!(config.delay(4), Duration::from_millis(800));
+    assert_eq!(config.delay(5), Duration::from_millis(1000));
+    assert_eq!(config.delay(64), Duration::from_millis(1000));
+
+    let config = RetryConfig {
+        jitter: true,
+        ..config
+    };
+    for _ in 0..100 {
+        let d = config.delay(3);
+        assert!(d >= Duration::from_millis(200) && d <= Duration::from_millis(400));
+    }
+}
+
+#[test]
+fn test_retry_stops_on_rejected() {
+    let config = RetryConfig {
+        base_delay: 1,
+        jitter: false,
+        ..Default::default()
+    };
+    let mut calls = 0;
+    let delivery = config.run("cpu", || {
+        calls += 1;
+        Delivery::Rejected
+    });
+    assert_eq!(delivery, Delivery::Rejected);
+    assert_eq!(calls, 1);
+
+    let mut calls = 0;
+    let delivery = config.run("cpu", || {
+        calls += 1;
+        Delivery::Unavailable
+    });
+    assert_eq!(delivery, Delivery::Unavailable);
+    assert_eq!(calls, 3);
+}
+
+#[test]
+fn test_retryable_status() {
+    let config = RetryConfig::default();
+    assert!(config.is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+    assert!(config.is_retryable(StatusCode::from_u16(599).unwrap()));
+    assert!(config.is_retryable(StatusCode::TOO_MANY_REQUESTS));
+    assert!(!config.is_retryable(StatusCode::BAD_REQUEST));
+
+    let config = RetryConfig {
+        retryable_status: vec![409],
+        ..config
+    };
+    assert!(config.is_retryable(StatusCode::CONFLICT));
+    assert!(config.is_retryable(StatusCode::INTERNAL_SERVER_ERROR));
+    assert!(!config.is_retryable(StatusCode::REQUEST_TIMEOUT));
+}
@@ -0,0 +1,114 @@
+//! 缓冲多次采集的结果并计算统计值
+//!
+//! 探针将每次采集的结果放入 `Window`，达到条数或间隔后连同 `rollup` 计算的最小、最大、平均值、
+//! p95及标准差一起提交，服务端不必重新计算。
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// 一组采样值的统计
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Rollup {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    ///第95百分位数(最近秩法)
+    pub p95: f64,
+    ///总体标准差
+    pub stddev: f64,
+}
+
+/// 计算统计值，没有采样值时返回None，忽略NaN
+pub fn rollup(values: &[f64]) -> Option<Rollup> {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+    if sorted.is_empty() {
+        return None;
+    }
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let count = sorted.len();
+    let mean = sorted.iter().sum::<f64>() / count as f64;
+    let variance = sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count as f64;
+    let rank = (count as f64 * 0.95).ceil() as usize;
+    Some(Rollup {
+        count,
+        min: sorted[0],
+        max: sorted[count - 1],
+        mean,
+        p95: sorted[rank.max(1) - 1],
+        stddev: variance.sqrt(),
+    })
+}
+
+/// 缓冲采集结果，达到条数或距第一条超过间隔时一起取出
+pub struct Window<T> {
+    items: Vec<T>,
+    started: Option<Instant>,
+}
+
+impl<T> Default for Window<T> {
+    fn default() -> Self {
+        Window::new()
+    }
+}
+
+impl<T> Window<T> {
+    pub fn new() -> Window<T> {
+        Window { items: vec![], started: None }
+    }
+
+    /// 加入一条，达到`size`条或距第一条超过`interval`(0为不限)时取出全部
+    pub fn push(&mut self, item: T, size: usize, interval: Duration) -> Option<Vec<T>> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        self.items.push(item);
+        let expired = !interval.is_zero() && started.elapsed() >= interval;
+        if self.items.len() >= size || expired {
+            return Some(self.take());
+        }
+        None
+    }
+
+    /// 取出全部，如程序退出前
+    pub fn take(&mut self) -> Vec<T> {
+        self.started = None;
+        std::mem::take(&mut self.items)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[test]
+fn test_rollup() {
+    assert_eq!(rollup(&[]), None);
+    assert_eq!(rollup(&[f64::NAN]), None);
+
+    let values: Vec<f64> = (1..=20).map(|v| v as f64).collect();
+    let r = rollup(&values).unwrap();
+    assert_eq!((r.count, r.min, r.max, r.mean, r.p95), (20, 1.0, 20.0, 10.5, 19.0));
+    assert!((r.stddev - 5.766281).abs() < 1e-6);
+
+    let r = rollup(&[3.0]).unwrap();
+    assert_eq!((r.min, r.max, r.p95, r.stddev), (3.0, 3.0, 3.0, 0.0));
+}
+
+#[test]
+fn test_window() {
+    let mut window = Window::new();
+    assert_eq!(window.push(1, 3, Duration::from_secs(60)), None);
+    assert_eq!(window.push(2, 3, Duration::from_secs(60)), None);
+    assert_eq!(window.push(3, 3, Duration::from_secs(60)), Some(vec![1, 2, 3]));
+    assert!(window.is_empty());
+
+    //超过间隔时不足条数也取出
+    assert_eq!(window.push(4, 10, Duration::from_millis(20)), None);
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(window.push(5, 10, Duration::from_millis(20)), Some(vec![4, 5]));
+    assert_eq!(window.push(6, 10, Duration::ZERO), None);
+    assert_eq!(window.take(), vec![6]);
+}
@@ -12,7 +12,8 @@
 //! ```
 //!
 //! Creating a schedule for a job is done using the `FromStr` impl for the
-//! `Schedule` type of the [cron](https://github.com/zslayton/cron) library.
+//! `Trigger` type, which accepts the expressions of the `Schedule` type of the
+//! [cron](https://github.com/zslayton/cron) library.
 //!
 //! The scheduling format is as follows:
 //!
@@ -55,22 +56,291 @@
 //!     }
 //! }
 //! ```
+//!
+//! Besides cron expressions, a job can run at a fixed interval written as
+//! `@every 30s` (units `s`, `m`, `h` and `d`, e.g. `@every 1h30m`), aligned to
+//! multiples of the interval since the Unix epoch. A random splay can be added
+//! per job with `Job::splay` so that many hosts sharing a schedule do not all
+//! fire at the same moment.
+//!
+//! Cron expressions follow the wall clock of the local time zone, or of the
+//! time zone set with `Job::timezone` or a `CRON_TZ=Europe/Berlin` prefix.
+//! Runs in the hour skipped when daylight saving time starts are moved to
+//! after the gap, and runs in the hour repeated when it ends happen once.
+//!
+//! Jobs created with `Job::spawn` run on a worker thread instead of the
+//! scheduler thread. A run still in progress when the job is due again is
+//! skipped, and runs exceeding the job's timeout are reported by `tick` as
+//! `JobEvent`s.
 
 extern crate chrono;
+extern crate chrono_tz;
 extern crate cron;
 extern crate uuid;
 
-use chrono::{offset, DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, Local, LocalResult, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
 pub use cron::Schedule;
+use rand::Rng;
+use std::str::FromStr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
 pub use uuid::Uuid;
 
+/// When a job runs.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub repeat: Repeat,
+    /// The time zone of a cron expression, given as a `CRON_TZ=<name>`
+    /// prefix. `None` falls back to the time zone of the job.
+    pub timezone: Option<Tz>,
+}
+
+/// How often a job runs.
+#[derive(Debug, Clone)]
+pub enum Repeat {
+    Cron(Box<Schedule>),
+    /// A fixed interval of at least one second. `parse_interval` also limits
+    /// it to `MAX_INTERVAL`.
+    Every(std::time::Duration),
+}
+
+impl Trigger {
+    /// The first run strictly after `after`. Cron expressions are evaluated
+    /// in the trigger's time zone, else in `timezone`, else in the local time
+    /// zone of the host. Intervals do not depend on the time zone.
+    pub fn next_after(&self, after: &DateTime<Utc>, timezone: Option<Tz>) -> Option<DateTime<Utc>> {
+        match &self.repeat {
+            Repeat::Cron(schedule) => match self.timezone.or(timezone) {
+                Some(tz) => next_in(schedule, after, &tz),
+                None => next_in(schedule, after, &Local),
+            },
+            Repeat::Every(interval) => {
+                // `None` rather than a panic for intervals chrono can not represent
+                let interval = i64::try_from(interval.as_millis()).ok().filter(|i| *i > 0)?;
+                let next = after.timestamp_millis().div_euclid(interval).checked_add(1)?.checked_mul(interval)?;
+                Utc.timestamp_millis_opt(next).single()
+            }
+        }
+    }
+}
+
+/// The first run of `schedule` strictly after `after`, in the wall-clock time
+/// of `tz`. A run falling into the gap when clocks go forward is moved by the
+/// length of the gap (02:30 becomes 03:30). A run falling into the hour that
+/// repeats when clocks go back only happens the first time.
+fn next_in<Z: TimeZone>(schedule: &Schedule, after: &DateTime<Utc>, tz: &Z) -> Option<DateTime<Utc>> {
+    let mut local = after.with_timezone(tz).naive_local();
+    loop {
+        // cron itself skips local times that do not exist or are ambiguous,
+        // so look for the next wall-clock time and map it back ourselves
+        let next = schedule.after(&Utc.from_utc_datetime(&local)).next()?.naive_utc();
+        let instant = match tz.from_local_datetime(&next) {
+            LocalResult::Single(t) => t.with_timezone(&Utc),
+            LocalResult::Ambiguous(first, _) => first.with_timezone(&Utc),
+            LocalResult::None => {
+                let before = tz.offset_from_utc_datetime(&(next - Duration::days(1))).fix();
+                Utc.from_utc_datetime(&(next - Duration::seconds(before.local_minus_utc() as i64)))
+            }
+        };
+        if instant > *after {
+            return Some(instant);
+        }
+        local = next;
+    }
+}
+
+impl From<Schedule> for Trigger {
+    fn from(schedule: Schedule) -> Self {
+        Trigger {
+            repeat: Repeat::Cron(Box::new(schedule)),
+            timezone: None,
+        }
+    }
+}
+
+impl From<std::time::Duration> for Trigger {
+    fn from(interval: std::time::Duration) -> Self {
+        Trigger {
+            repeat: Repeat::Every(interval),
+            timezone: None,
+        }
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    /// Parse `@every <interval>` or a cron expression, optionally prefixed
+    /// with the time zone of the cron expression, e.g.
+    /// `CRON_TZ=Asia/Shanghai 0 0 2 * * ?`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (timezone, s) = match s.trim().strip_prefix(CRON_TZ) {
+            Some(rest) => {
+                let (name, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                (Some(parse_timezone(name)?), rest)
+            }
+            None => (None, s),
+        };
+        let trigger = match s.trim().strip_prefix("@every") {
+            Some(interval) => parse_interval(interval).map(Trigger::from)?,
+            None => s.trim().parse::<Schedule>().map(Trigger::from).map_err(|e| e.to_string())?,
+        };
+        Ok(Trigger { timezone, ..trigger })
+    }
+}
+
+/// Prefix of a trigger giving the time zone of its cron expression.
+pub const CRON_TZ: &str = "CRON_TZ=";
+
+/// Parse an IANA time zone name such as `Asia/Shanghai` or `UTC`.
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.trim().parse::<Tz>().map_err(|_| format!("unknown time zone `{}`, expected an IANA name such as Asia/Shanghai", name.trim()))
+}
+
+/// The longest interval accepted by `parse_interval`, in seconds (365 days).
+pub const MAX_INTERVAL: u64 = 365 * 86400;
+
+/// Parse an interval such as `30s`, `10m` or `1h30m`, at most `MAX_INTERVAL`.
+pub fn parse_interval(s: &str) -> Result<std::time::Duration, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("interval is empty".to_string());
+    }
+    let mut secs: u64 = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(format!("unknown unit `{}` in `{}`, expected s, m, h or d", c, s)),
+        };
+        let n: u64 = number.parse().map_err(|_| format!("missing number before `{}` in `{}`", c, s))?;
+        secs = n.checked_mul(unit).and_then(|n| secs.checked_add(n)).ok_or_else(|| format!("`{}` is too large", s))?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("missing unit after `{}` in `{}`", number, s));
+    }
+    if secs == 0 {
+        return Err("interval must be at least 1s".to_string());
+    }
+    if secs > MAX_INTERVAL {
+        return Err(format!("`{}` is too large, the interval must be at most 365d", s));
+    }
+    Ok(std::time::Duration::from_secs(secs))
+}
+
+/// Something that happened to a worker job during `JobScheduler::tick`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobEvent {
+    /// The job was due while its previous run was still in progress.
+    Skipped { job_id: Uuid, running: std::time::Duration },
+    /// The current run has exceeded the job's timeout. Reported once per run.
+    TimedOut { job_id: Uuid, elapsed: std::time::Duration },
+}
+
+/// What a job returns: `()`, or a `Result` whose error is recorded as the
+/// job's `last_error`.
+pub trait RunResult {
+    fn into_error(self) -> Option<String>;
+}
+
+impl RunResult for () {
+    fn into_error(self) -> Option<String> {
+        None
+    }
+}
+
+impl<E: std::fmt::Display> RunResult for Result<(), E> {
+    fn into_error(self) -> Option<String> {
+        self.err().map(|e| e.to_string())
+    }
+}
+
+/// What a job has done so far, see `Job::stats`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobStats {
+    /// When the last run started.
+    pub last_start: Option<DateTime<Utc>>,
+    /// How long the last finished run took.
+    pub last_duration: Option<std::time::Duration>,
+    /// The error of the last finished run, `None` if it succeeded. A panic of
+    /// a worker job is recorded as an error.
+    pub last_error: Option<String>,
+    /// Runs started so far.
+    pub runs: u64,
+    /// Runs that were due but did not start, because the previous run was
+    /// still in progress or because of `limit_missed_runs`.
+    pub missed_runs: u64,
+    /// Whether a run of a worker job is in progress.
+    pub running: bool,
+    /// The next scheduled run, including the splay.
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+type Work = Arc<Mutex<dyn FnMut() -> Option<String> + Send>>;
+
+enum Runner<'a> {
+    /// Runs on the scheduler thread.
+    Inline(Box<dyn FnMut() -> Option<String> + 'a>),
+    /// Runs on a worker thread.
+    Worker(Work),
+}
+
+/// A finished run of a worker job.
+struct Finished {
+    duration: std::time::Duration,
+    error: Option<String>,
+}
+
+/// A run in progress on a worker thread.
+struct Running {
+    started: Instant,
+    /// Set by the worker thread, even if the job panics.
+    finished: Arc<Mutex<Option<Finished>>>,
+    timed_out: bool,
+}
+
+impl Running {
+    fn is_done(&self) -> bool {
+        self.finished.lock().unwrap().is_some()
+    }
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    let message = match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => panic.downcast_ref::<&str>().map(|s| s.to_string()).unwrap_or_default(),
+    };
+    format!("panicked: {}", message)
+}
+
 /// A schedulable `Job`.
 pub struct Job<'a> {
-    schedule: Schedule,
-    run: Box<dyn (FnMut() -> ()) + 'a>,
+    schedule: Trigger,
+    run: Runner<'a>,
     last_tick: Option<DateTime<Utc>>,
     limit_missed_runs: usize,
     job_id: Uuid,
+    timeout: Option<std::time::Duration>,
+    skip_if_running: bool,
+    running: Option<Running>,
+    /// A run queued while the previous one is in progress, see `skip_if_running`.
+    pending: bool,
+    splay: std::time::Duration,
+    ///Runs are delayed by this random part of `splay`.
+    offset: Duration,
+    timezone: Option<Tz>,
+    stats: JobStats,
 }
 
 impl<'a> Job<'a> {
@@ -82,45 +352,249 @@ impl<'a> Job<'a> {
     /// let s: Schedule = "0 15 6,8,10 * Mar,Jun Fri 2017".into().unwrap();
     /// Job::new(s, || println!("I have a complex schedule...") );
     /// ```
-    pub fn new<T>(schedule: Schedule, run: T) -> Job<'a>
+    pub fn new<T, R>(schedule: Trigger, mut run: T) -> Job<'a>
     where
         T: 'a,
-        T: FnMut() -> (),
+        T: FnMut() -> R,
+        R: RunResult,
+    {
+        Job::with_runner(schedule, Runner::Inline(Box::new(move || run().into_error())))
+    }
+
+    /// Create a new job that runs on a worker thread, so a slow run does not
+    /// hold up the other jobs of the scheduler.
+    ///
+    /// ```rust,ignore
+    /// let mut job = Job::spawn("0/10 * * * * *".parse().unwrap(), || {
+    ///     println!("I run on a worker thread");
+    /// });
+    /// job.timeout(Some(std::time::Duration::from_secs(30)));
+    /// ```
+    pub fn spawn<T, R>(schedule: Trigger, mut run: T) -> Job<'a>
+    where
+        T: 'static,
+        T: FnMut() -> R + Send,
+        R: RunResult,
     {
+        Job::with_runner(schedule, Runner::Worker(Arc::new(Mutex::new(move || run().into_error()))))
+    }
+
+    fn with_runner(schedule: Trigger, run: Runner<'a>) -> Job<'a> {
         Job {
             schedule,
-            run: Box::new(run),
+            run,
             last_tick: None,
             limit_missed_runs: 1,
             job_id: Uuid::new_v4(),
+            timeout: None,
+            skip_if_running: true,
+            running: None,
+            pending: false,
+            splay: std::time::Duration::from_secs(0),
+            offset: Duration::zero(),
+            timezone: None,
+            stats: JobStats::default(),
         }
     }
 
-    fn tick(&mut self) {
-        let now = Utc::now();
+    /// The next scheduled run after `now`, including the splay.
+    fn next_run(&self, now: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let now = now.checked_sub_signed(self.offset)?;
+        self.schedule.next_after(&now, self.timezone).and_then(|t| t.checked_add_signed(self.offset))
+    }
+
+    fn tick(&mut self, events: &mut Vec<JobEvent>) {
+        self.check_running(events);
+
+        //work in the schedule's own time, so that the splay delays every run
+        let now = match Utc::now().checked_sub_signed(self.offset) {
+            Some(now) => now,
+            None => return,
+        };
         if self.last_tick.is_none() {
             self.last_tick = Some(now);
             return;
         }
-        if self.limit_missed_runs > 0 {
-            for event in self.schedule.after(&self.last_tick.unwrap()).take(self.limit_missed_runs) {
-                if event > now {
-                    break;
-                }
-                (self.run)();
+        let limit = if self.limit_missed_runs > 0 { self.limit_missed_runs } else { usize::MAX };
+        let mut due = 0;
+        let mut last = self.last_tick.unwrap();
+        //keep counting past the limit, the runs beyond it are missed
+        while let Some(event) = self.schedule.next_after(&last, self.timezone).filter(|e| *e <= now) {
+            if due < limit {
+                due += 1;
+            } else {
+                self.stats.missed_runs += 1;
             }
-        } else {
-            for event in self.schedule.after(&self.last_tick.unwrap()) {
-                if event > now {
-                    break;
-                }
-                (self.run)();
+            last = event;
+        }
+        for _ in 0..due {
+            if let Some(event) = self.run_now() {
+                events.push(event);
             }
         }
 
         self.last_tick = Some(now);
     }
 
+    /// Record a finished run of a worker job.
+    fn check_finished(&mut self) {
+        let finished = match &self.running {
+            Some(running) => running.finished.lock().unwrap().take(),
+            None => return,
+        };
+        if let Some(finished) = finished {
+            self.stats.last_duration = Some(finished.duration);
+            self.stats.last_error = finished.error;
+            self.running = None;
+        }
+    }
+
+    /// Record a finished run, start the queued run and report a run that has
+    /// exceeded the timeout.
+    fn check_running(&mut self, events: &mut Vec<JobEvent>) {
+        self.check_finished();
+        if self.running.is_none() && self.pending {
+            self.pending = false;
+            if let Runner::Worker(work) = &self.run {
+                let work = work.clone();
+                self.start(work);
+            }
+        }
+        let running = match self.running.as_mut() {
+            Some(running) => running,
+            None => return,
+        };
+        let elapsed = running.started.elapsed();
+        if let Some(timeout) = self.timeout {
+            if !running.timed_out && elapsed > timeout {
+                running.timed_out = true;
+                events.push(JobEvent::TimedOut { job_id: self.job_id, elapsed });
+            }
+        }
+    }
+
+    /// Run the job immediately, regardless of its schedule. For a worker job
+    /// whose previous run is still in progress, returns the `Skipped` event
+    /// instead if `skip_if_running` is set, or if a run is already queued.
+    pub fn run_now(&mut self) -> Option<JobEvent> {
+        let work = match &mut self.run {
+            Runner::Inline(run) => {
+                let started = Instant::now();
+                self.stats.last_start = Some(Utc::now());
+                self.stats.runs += 1;
+                self.stats.last_error = run();
+                self.stats.last_duration = Some(started.elapsed());
+                return None;
+            }
+            Runner::Worker(work) => work.clone(),
+        };
+        self.check_finished();
+        if let Some(running) = &self.running {
+            if !self.skip_if_running && !self.pending {
+                self.pending = true;
+                return None;
+            }
+            self.stats.missed_runs += 1;
+            return Some(JobEvent::Skipped {
+                job_id: self.job_id,
+                running: running.started.elapsed(),
+            });
+        }
+        self.start(work);
+        None
+    }
+
+    /// Start a run of a worker job on a new worker thread.
+    fn start(&mut self, work: Work) {
+        let finished = Arc::new(Mutex::new(None));
+        let slot = finished.clone();
+        thread::spawn(move || {
+            let mut run = work.lock().unwrap_or_else(|e| e.into_inner());
+            let started = Instant::now();
+            let error = match panic::catch_unwind(AssertUnwindSafe(|| (*run)())) {
+                Ok(error) => error,
+                Err(panic) => Some(panic_message(panic)),
+            };
+            *slot.lock().unwrap() = Some(Finished {
+                duration: started.elapsed(),
+                error,
+            });
+        });
+        self.stats.last_start = Some(Utc::now());
+        self.stats.runs += 1;
+        self.running = Some(Running {
+            started: Instant::now(),
+            finished,
+            timed_out: false,
+        });
+    }
+
+    /// Whether a run of this worker job is still in progress.
+    pub fn is_running(&self) -> bool {
+        self.running.as_ref().map(|r| !r.is_done()).unwrap_or(false)
+    }
+
+    /// What the job has done so far and when it runs next.
+    pub fn stats(&self) -> JobStats {
+        JobStats {
+            running: self.is_running(),
+            next_run: self.next_run(&Utc::now()),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Set the time after which a run of a worker job is reported as
+    /// `TimedOut`. `None` means no limit. The run itself can not be aborted;
+    /// it keeps counting as running until it returns.
+    pub fn timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Whether a worker job that is due while its previous run is still in
+    /// progress is skipped (the default). Otherwise one run is queued and
+    /// starts on the first `tick` after the previous one has finished; runs
+    /// due while a run is already queued are skipped.
+    pub fn skip_if_running(&mut self, skip: bool) {
+        self.skip_if_running = skip;
+    }
+
+    /// Delay every run by a random duration below `splay`, chosen once per
+    /// job so the runs stay evenly spaced. Setting the same value again keeps
+    /// the current delay. A splay above `MAX_INTERVAL` is capped to it.
+    ///
+    /// ```rust,ignore
+    /// let mut job = Job::new("0 0 0 * * ?".parse().unwrap(), || {
+    ///     println!("I run once a day, some time in the first 10 minutes after midnight");
+    /// });
+    /// job.splay(std::time::Duration::from_secs(600));
+    /// ```
+    pub fn splay(&mut self, splay: std::time::Duration) {
+        if splay == self.splay {
+            return;
+        }
+        let last_tick = self.last_tick.and_then(|t| t.checked_add_signed(self.offset));
+        self.splay = splay;
+        let max = i64::try_from(splay.as_millis()).unwrap_or(i64::MAX).min(MAX_INTERVAL as i64 * 1000);
+        self.offset = match max {
+            0 => Duration::zero(),
+            max => Duration::milliseconds(rand::thread_rng().gen_range(0..max)),
+        };
+        self.last_tick = last_tick.and_then(|t| t.checked_sub_signed(self.offset));
+    }
+
+    /// Set the time zone of cron expressions without a `CRON_TZ=` prefix.
+    /// `None` (the default) is the local time zone of the host.
+    ///
+    /// ```rust,ignore
+    /// let mut job = Job::new("0 0 2 * * ?".parse().unwrap(), || {
+    ///     println!("I run at 2am in New York, or 3am when that does not exist");
+    /// });
+    /// job.timezone(Some(chrono_tz::America::New_York));
+    /// ```
+    pub fn timezone(&mut self, timezone: Option<Tz>) {
+        self.timezone = timezone;
+    }
+
     /// Set the limit for missed jobs in the case of delayed runs. Setting to 0 means unlimited.
     ///
     /// ```rust,ignore
@@ -210,15 +684,29 @@ impl<'a> JobScheduler<'a> {
     ///     std::thread::sleep(Duration::from_millis(500));
     /// }
     /// ```
-    pub fn tick(&mut self) {
+    ///
+    /// Returns the skipped and timed out runs of worker jobs.
+    pub fn tick(&mut self) -> Vec<JobEvent> {
+        let mut events = vec![];
         for job in &mut self.jobs {
-            job.tick();
+            job.tick(&mut events);
         }
+        events
+    }
+
+    /// Get a job to read its stats.
+    pub fn job(&self, job_id: Uuid) -> Option<&Job<'a>> {
+        self.jobs.iter().find(|job| job.job_id == job_id)
+    }
+
+    /// Get a job to change its settings or run it immediately.
+    pub fn job_mut(&mut self, job_id: Uuid) -> Option<&mut Job<'a>> {
+        self.jobs.iter_mut().find(|job| job.job_id == job_id)
     }
 
     /// The `time_till_next_job` method returns the duration till the next job
     /// is supposed to run. This can be used to sleep until then without waking
-    /// up at a fixed interval.AsMut
+    /// up at a fixed interval.
     ///
     /// ```rust, ignore
     /// loop {
@@ -231,16 +719,283 @@ impl<'a> JobScheduler<'a> {
             // Take a guess if there are no jobs.
             return std::time::Duration::from_millis(500);
         }
-        let mut duration = Duration::zero();
         let now = Utc::now();
-        for job in self.jobs.iter() {
-            for event in job.schedule.upcoming(offset::Utc).take(1) {
-                let d = event - now;
-                if duration.is_zero() || d < duration {
-                    duration = d;
-                }
-            }
+        let next = self.jobs.iter().filter_map(|job| job.next_run(&now)).min();
+        match next {
+            Some(next) => (next - now).to_std().unwrap_or_default(),
+            None => std::time::Duration::from_millis(500),
+        }
+    }
+}
+
+#[test]
+fn test_worker_job() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+
+    let runs = Arc::new(AtomicUsize::new(0));
+    let (release, wait) = mpsc::channel::<()>();
+    let wait = Mutex::new(wait);
+    let mut job = Job::spawn("0 0 0 1 1 ?".parse().unwrap(), {
+        let runs = runs.clone();
+        move || {
+            runs.fetch_add(1, Ordering::SeqCst);
+            wait.lock().unwrap().recv().ok();
+        }
+    });
+    job.timeout(Some(std::time::Duration::from_millis(100)));
+    let job_id = job.job_id;
+    let mut sched = JobScheduler::new();
+    sched.add(job);
+
+    // runs without blocking the scheduler thread
+    assert_eq!(sched.job_mut(job_id).unwrap().run_now(), None);
+    assert!(sched.job_mut(job_id).unwrap().is_running());
+
+    // skipped while still running
+    match sched.job_mut(job_id).unwrap().run_now() {
+        Some(JobEvent::Skipped { job_id: id, .. }) => assert_eq!(id, job_id),
+        e => panic!("{:?}", e),
+    }
+
+    // timeout is reported once per run
+    thread::sleep(std::time::Duration::from_millis(150));
+    let events = sched.tick();
+    assert!(matches!(events.as_slice(), [JobEvent::TimedOut { job_id: id, .. }] if *id == job_id));
+    assert!(sched.tick().iter().all(|e| !matches!(e, JobEvent::TimedOut { .. })));
+
+    // runs again once finished
+    release.send(()).unwrap();
+    for _ in 0..50 {
+        if !sched.job_mut(job_id).unwrap().is_running() {
+            break;
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(!sched.job_mut(job_id).unwrap().is_running());
+    assert_eq!(sched.job_mut(job_id).unwrap().run_now(), None);
+    release.send(()).unwrap();
+    for _ in 0..50 {
+        if runs.load(Ordering::SeqCst) == 2 {
+            break;
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_trigger() {
+    assert_eq!(parse_interval("30s"), Ok(std::time::Duration::from_secs(30)));
+    assert_eq!(parse_interval(" 1h30m "), Ok(std::time::Duration::from_secs(5400)));
+    assert_eq!(parse_interval("2d"), Ok(std::time::Duration::from_secs(172800)));
+    assert!(parse_interval("0s").is_err());
+    assert!(parse_interval("10").is_err());
+    assert!(parse_interval("m").is_err());
+    assert!(parse_interval("10w").is_err());
+    assert_eq!(parse_interval("365d"), Ok(std::time::Duration::from_secs(MAX_INTERVAL)));
+    assert!(parse_interval("365d1s").is_err());
+    assert!(parse_interval("100000000d").is_err());
+    assert!(parse_interval("99999999999999999999s").is_err());
+
+    let every: Trigger = "@every 10m".parse().unwrap();
+    let t = Utc.ymd(2021, 1, 1).and_hms(8, 3, 20);
+    assert_eq!(every.next_after(&t, None), Some(Utc.ymd(2021, 1, 1).and_hms(8, 10, 0)));
+    assert_eq!(every.next_after(&Utc.ymd(2021, 1, 1).and_hms(8, 10, 0), None), Some(Utc.ymd(2021, 1, 1).and_hms(8, 20, 0)));
+    //intervals built directly are not limited, but must not panic
+    let huge = Trigger::from(std::time::Duration::from_secs(100_000_000 * 86400));
+    assert_eq!(huge.next_after(&t, None), None);
+    assert_eq!(Trigger::from(std::time::Duration::from_secs(u64::MAX)).next_after(&t, None), None);
+
+    let cron: Trigger = "0 0 0 * * ?".parse().unwrap();
+    assert_eq!(cron.next_after(&t, Some(Tz::UTC)), Some(Utc.ymd(2021, 1, 2).and_hms(0, 0, 0)));
+    assert_eq!(cron.next_after(&t, Some(Tz::Asia__Shanghai)), Some(Utc.ymd(2021, 1, 1).and_hms(16, 0, 0)));
+    assert!("@every".parse::<Trigger>().is_err());
+    assert!("every 10 seconds".parse::<Trigger>().is_err());
+
+    //the prefix wins over the time zone of the job
+    let zoned: Trigger = "CRON_TZ=Asia/Shanghai 0 0 0 * * ?".parse().unwrap();
+    assert_eq!(zoned.timezone, Some(Tz::Asia__Shanghai));
+    assert_eq!(zoned.next_after(&t, Some(Tz::UTC)), Some(Utc.ymd(2021, 1, 1).and_hms(16, 0, 0)));
+    assert!("CRON_TZ=Mars/Olympus 0 0 0 * * ?".parse::<Trigger>().is_err());
+    assert!("CRON_TZ=UTC".parse::<Trigger>().is_err());
+}
+
+#[test]
+fn test_timezone_dst() {
+    use chrono::Timelike;
+
+    let new_york = Some(Tz::America__New_York);
+    let daily: Trigger = "0 30 2 * * ?".parse().unwrap();
+    //2021-03-14 02:00 EST jumps to 03:00 EDT, 02:30 is moved to 03:30 EDT
+    let before = Utc.ymd(2021, 3, 13).and_hms(12, 0, 0);
+    let gap = daily.next_after(&before, new_york).unwrap();
+    assert_eq!(gap, Utc.ymd(2021, 3, 14).and_hms(7, 30, 0));
+    assert_eq!(daily.next_after(&gap, new_york), Some(Utc.ymd(2021, 3, 15).and_hms(6, 30, 0)));
+
+    //every minute during the gap runs once, right after it
+    let minutely: Trigger = "0 * * * * ?".parse().unwrap();
+    let t = minutely.next_after(&Utc.ymd(2021, 3, 14).and_hms(6, 59, 0), new_york).unwrap();
+    assert_eq!(t, Utc.ymd(2021, 3, 14).and_hms(7, 0, 0));
+    assert_eq!(minutely.next_after(&t, new_york), Some(Utc.ymd(2021, 3, 14).and_hms(7, 1, 0)));
+
+    //2021-11-07 02:00 EDT goes back to 01:00 EST, 01:30 only runs the first time
+    let daily: Trigger = "0 30 1 * * ?".parse().unwrap();
+    let before = Utc.ymd(2021, 11, 6).and_hms(12, 0, 0);
+    let first = daily.next_after(&before, new_york).unwrap();
+    assert_eq!(first, Utc.ymd(2021, 11, 7).and_hms(5, 30, 0));
+    assert_eq!(daily.next_after(&first, new_york), Some(Utc.ymd(2021, 11, 8).and_hms(6, 30, 0)));
+    let repeated = Utc.ymd(2021, 11, 7).and_hms(6, 10, 0);
+    assert_eq!(daily.next_after(&repeated, new_york), Some(Utc.ymd(2021, 11, 8).and_hms(6, 30, 0)));
+
+    //01:00 of an hourly job also runs once, the next run after 01:30 EDT is 02:00 EST
+    let hourly: Trigger = "0 0 * * * ?".parse().unwrap();
+    let t = hourly.next_after(&Utc.ymd(2021, 11, 7).and_hms(5, 30, 0), new_york).unwrap();
+    assert_eq!(t, Utc.ymd(2021, 11, 7).and_hms(7, 0, 0));
+    assert_eq!(hourly.next_after(&t, new_york), Some(Utc.ymd(2021, 11, 7).and_hms(8, 0, 0)));
+
+    //the job and `time_till_next_job` use the time zone of the job
+    let mut job = Job::new(daily.clone(), || {});
+    job.timezone(new_york);
+    assert_eq!(job.next_run(&before), Some(first));
+
+    let mut sched = JobScheduler::new();
+    let mut job = Job::new("0 0 * * * ?".parse().unwrap(), || {});
+    job.timezone(Some(Tz::Asia__Kolkata));
+    sched.add(job);
+    let next = Utc::now() + Duration::from_std(sched.time_till_next_job()).unwrap() + Duration::milliseconds(500);
+    assert_eq!((next.minute(), next.second()), (30, 0));
+}
+
+#[test]
+fn test_splay() {
+    let mut job = Job::new("0 0 0 * * ?".parse().unwrap(), || {});
+    job.splay(std::time::Duration::from_secs(600));
+    let offset = job.offset;
+    assert!(offset >= Duration::zero() && offset < Duration::seconds(600));
+    job.splay(std::time::Duration::from_secs(600));
+    assert_eq!(job.offset, offset);
+
+    //an oversized splay is capped instead of overflowing
+    let mut capped = Job::new("0 0 0 * * ?".parse().unwrap(), || {});
+    capped.splay(std::time::Duration::MAX);
+    assert!(capped.offset >= Duration::zero() && capped.offset < Duration::seconds(MAX_INTERVAL as i64));
+    assert!(capped.next_run(&Utc::now()).is_some());
+
+    let now = Utc.ymd(2021, 1, 1).and_hms(23, 0, 0);
+    assert_eq!(job.next_run(&now), Some(Utc.ymd(2021, 1, 2).and_hms(0, 0, 0) + offset));
+    //a run delayed past midnight is not skipped
+    let after_midnight = Utc.ymd(2021, 1, 2).and_hms(0, 0, 0) + offset - Duration::milliseconds(1);
+    assert_eq!(job.next_run(&after_midnight), Some(Utc.ymd(2021, 1, 2).and_hms(0, 0, 0) + offset));
+
+    let mut sched = JobScheduler::new();
+    sched.add(Job::new("@every 1h".parse().unwrap(), || {}));
+    assert!(sched.time_till_next_job() <= std::time::Duration::from_secs(3600));
+}
+
+#[test]
+fn test_job_stats() {
+    //inline runs record their result, runs beyond `limit_missed_runs` are missed
+    let mut fail = false;
+    let mut job = Job::new("* * * * * *".parse().unwrap(), move || {
+        fail = !fail;
+        if fail {
+            Err("failed")
+        } else {
+            Ok(())
+        }
+    });
+    assert_eq!(job.stats().runs, 0);
+    job.last_tick(Some(Utc::now() - Duration::seconds(10)));
+    job.tick(&mut vec![]);
+    let stats = job.stats();
+    assert_eq!(stats.runs, 1);
+    assert!((9..=10).contains(&stats.missed_runs));
+    assert_eq!(stats.last_error.as_deref(), Some("failed"));
+    assert!(stats.last_start.is_some() && stats.last_duration.is_some());
+    assert!(stats.next_run.unwrap() <= Utc::now() + Duration::seconds(1));
+    job.run_now();
+    assert_eq!(job.stats().last_error, None);
+
+    //a panic of a worker job is recorded, skipped runs are missed
+    let (release, wait) = std::sync::mpsc::channel::<()>();
+    let wait = Mutex::new(wait);
+    let mut job = Job::spawn("0 0 0 1 1 ?".parse().unwrap(), move || -> Result<(), String> {
+        wait.lock().unwrap().recv().ok();
+        panic!("boom")
+    });
+    job.run_now();
+    assert!(job.stats().running);
+    assert!(matches!(job.run_now(), Some(JobEvent::Skipped { .. })));
+    assert_eq!((job.stats().runs, job.stats().missed_runs), (1, 1));
+    release.send(()).unwrap();
+    for _ in 0..50 {
+        job.tick(&mut vec![]);
+        if !job.is_running() {
+            break;
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+    job.tick(&mut vec![]);
+    let stats = job.stats();
+    assert!(!stats.running);
+    assert_eq!(stats.last_error.as_deref(), Some("panicked: boom"));
+}
+
+#[test]
+fn test_queued_run() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+
+    let runs = Arc::new(AtomicUsize::new(0));
+    let (release, wait) = mpsc::channel::<()>();
+    let wait = Mutex::new(wait);
+    let mut job = Job::spawn("0 0 0 1 1 ?".parse().unwrap(), {
+        let runs = runs.clone();
+        move || -> Result<(), String> {
+            let run = runs.fetch_add(1, Ordering::SeqCst) + 1;
+            wait.lock().unwrap().recv().ok();
+            Err(format!("run {}", run))
+        }
+    });
+    job.skip_if_running(false);
+    job.timeout(Some(std::time::Duration::from_millis(50)));
+
+    //one run is queued behind the running one, the next is skipped
+    assert_eq!(job.run_now(), None);
+    assert_eq!(job.run_now(), None);
+    assert!(matches!(job.run_now(), Some(JobEvent::Skipped { .. })));
+    assert_eq!((job.stats().runs, job.stats().missed_runs), (1, 1));
+
+    //the running run still times out
+    thread::sleep(std::time::Duration::from_millis(100));
+    let mut events = vec![];
+    job.tick(&mut events);
+    assert!(matches!(events.as_slice(), [JobEvent::TimedOut { .. }]));
+
+    //the queued run starts once the first has finished, which is recorded
+    release.send(()).unwrap();
+    for _ in 0..50 {
+        job.tick(&mut vec![]);
+        if job.stats().runs == 2 {
+            break;
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let stats = job.stats();
+    assert_eq!(stats.runs, 2);
+    assert_eq!(stats.last_error.as_deref(), Some("run 1"));
+    assert!(stats.last_duration.is_some());
+
+    release.send(()).unwrap();
+    for _ in 0..50 {
+        job.tick(&mut vec![]);
+        if !job.is_running() {
+            break;
         }
-        duration.to_std().unwrap()
+        thread::sleep(std::time::Duration::from_millis(10));
     }
+    job.tick(&mut vec![]);
+    assert_eq!(job.stats().last_error.as_deref(), Some("run 2"));
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
 }
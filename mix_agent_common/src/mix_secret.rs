@@ -0,0 +1,195 @@
+//! 配置中的密文及引用
+//!
+//! 加载配置时(`mix_config::read`)透明替换以下形式的配置值，替换失败时返回该配置项的错误：
+//!
+//! * `ENC(...)`：`mix_agent_tool encrypt` 生成的AES-256-GCM密文
+//! * `${file:/etc/mix/secret}`：文件内容，去掉首尾空白
+//! * `${env:MIX_SECRET}`：环境变量
+//!
+//! 密钥由密钥文件(默认为配置目录/mix.key，见 `mix_override::key_file`)中的随机数与本机标识派生，
+//! 密文与密钥文件复制到其他主机后无法解密。
+
+use crate::mix_override::key_file;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use config::Config;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde_json::Value;
+use sha2::Sha256;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const NONCE_LEN: usize = 12;
+
+/// 本机标识，优先使用machine-id，其次为主机名
+pub fn host_id() -> String {
+    for path in ["/etc/machine-id", "/var/lib/dbus/machine-id", "/etc/hostname"].iter() {
+        if let Ok(id) = fs::read_to_string(path) {
+            if !id.trim().is_empty() {
+                return id.trim().to_string();
+            }
+        }
+    }
+    env::var("COMPUTERNAME").or_else(|_| env::var("HOSTNAME")).unwrap_or_default()
+}
+
+/// 从密钥文件及本机标识派生密钥
+fn derive(seed: &[u8], host_id: &str) -> Aes256Gcm {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(seed).expect("HMAC可接受任意长度的密钥");
+    mac.update(host_id.as_bytes());
+    Aes256Gcm::new(&mac.finalize().into_bytes())
+}
+
+/// 读取密钥文件
+fn read_seed(path: &Path) -> io::Result<Vec<u8>> {
+    let content = fs::read_to_string(path)?;
+    hex::decode(content.trim()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// 读取密钥文件，不存在时生成，仅当前用户可读写
+pub fn ensure_key_file(path: &Path) -> io::Result<Vec<u8>> {
+    if path.exists() {
+        return read_seed(path);
+    }
+    let mut seed = vec![0u8; 32];
+    rand::thread_rng().fill_bytes(&mut seed);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, hex::encode(&seed))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(seed)
+}
+
+/// 加密，返回`ENC(...)`
+pub fn encrypt(seed: &[u8], host_id: &str, plain: &str) -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = derive(seed, host_id).encrypt(Nonce::from_slice(&nonce), plain.as_bytes()).expect("AES-GCM加密不会失败");
+    format!("ENC({}{})", hex::encode(nonce), hex::encode(cipher))
+}
+
+/// 解密`ENC(...)`中的内容
+pub fn decrypt(seed: &[u8], host_id: &str, data: &str) -> Result<String, String> {
+    let bytes = hex::decode(data).map_err(|_| "密文格式错误".to_string())?;
+    if bytes.len() <= NONCE_LEN {
+        return Err("密文格式错误".to_string());
+    }
+    let (nonce, cipher) = bytes.split_at(NONCE_LEN);
+    let plain = derive(seed, host_id).decrypt(Nonce::from_slice(nonce), cipher).map_err(|_| "解密失败，密钥文件不匹配或不是在本机加密".to_string())?;
+    String::from_utf8(plain).map_err(|_| "解密结果不是有效的UTF-8".to_string())
+}
+
+/// 替换单个配置值，无需替换时返回None
+fn resolve<E: Fn(&str) -> Option<String>>(value: &str, key_file: &Path, env: &E, seed: &mut Option<Vec<u8>>) -> Option<Result<String, String>> {
+    if let Some(data) = value.strip_prefix("ENC(").and_then(|v| v.strip_suffix(')')) {
+        if seed.is_none() {
+            match read_seed(key_file) {
+                Ok(s) => *seed = Some(s),
+                Err(e) => return Some(Err(format!("无法读取密钥文件`{}`: {}", key_file.display(), e))),
+            }
+        }
+        return Some(decrypt(seed.as_ref().unwrap(), &host_id(), data));
+    }
+    if let Some(path) = value.strip_prefix("${file:").and_then(|v| v.strip_suffix('}')) {
+        return Some(fs::read_to_string(path).map(|s| s.trim().to_string()).map_err(|e| format!("无法读取文件`{}`: {}", path, e)));
+    }
+    if let Some(name) = value.strip_prefix("${env:").and_then(|v| v.strip_suffix('}')) {
+        return Some(env(name).ok_or_else(|| format!("环境变量`{}`未设置", name)));
+    }
+    None
+}
+
+/// 收集需要替换的字符串配置项，路径与配置项路径写法相同，如`auth.keys.password`、`sinks[0].address`
+fn collect(value: &Value, path: String, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::String(s) if s.starts_with("ENC(") || s.starts_with("${") => out.push((path, s.clone())),
+        Value::Object(map) => {
+            for (k, v) in map.iter() {
+                let child = if path.is_empty() { k.clone() } else { format!("{}.{}", path, k) };
+                collect(v, child, out);
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                collect(v, format!("{}[{}]", path, i), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 替换配置中的密文及引用，失败时返回(配置项路径, 原因)
+pub fn apply(c: &mut Config) -> Result<(), (String, String)> {
+    apply_with(c, &key_file(), |name| env::var(name).ok())
+}
+
+/// 同`apply`，指定密钥文件及环境变量的读取方式
+fn apply_with<E: Fn(&str) -> Option<String>>(c: &mut Config, key_file: &Path, env: E) -> Result<(), (String, String)> {
+    let tree = match c.clone().try_into::<Value>() {
+        Ok(tree) => tree,
+        //交由后续解析报告错误
+        Err(_) => return Ok(()),
+    };
+    let mut values = vec![];
+    collect(&tree, String::new(), &mut values);
+
+    let mut seed = None;
+    for (path, value) in values {
+        if let Some(resolved) = resolve(&value, key_file, &env, &mut seed) {
+            let resolved = resolved.map_err(|e| (path.clone(), e))?;
+            c.set(&path, resolved).map_err(|e| (path.clone(), e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_decrypt() {
+    let seed = vec![7u8; 32];
+    let enc = encrypt(&seed, "host-a", "1234513abABqe131413");
+    assert!(enc.starts_with("ENC(") && enc.ends_with(')'));
+    assert_ne!(enc, encrypt(&seed, "host-a", "1234513abABqe131413"));
+
+    let data = &enc[4..enc.len() - 1];
+    assert_eq!(decrypt(&seed, "host-a", data).unwrap(), "1234513abABqe131413");
+    assert!(decrypt(&seed, "host-b", data).unwrap_err().starts_with("解密失败"));
+    assert!(decrypt(&[8u8; 32], "host-a", data).is_err());
+    assert_eq!(decrypt(&seed, "host-a", "zz").unwrap_err(), "密文格式错误");
+}
+
+#[test]
+fn test_apply_references() {
+    let dir = env::temp_dir().join(format!("mix_secret_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let secret = dir.join("secret");
+    fs::write(&secret, "s3cret\n").unwrap();
+    //不修改进程的环境变量，避免影响并行执行的其他测试
+    let env = |name: &str| (name == "MIX_TEST_SECRET_USER").then(|| "admin".to_string());
+
+    let key = dir.join("mix.key");
+    let seed = ensure_key_file(&key).unwrap();
+    let token = encrypt(&seed, &host_id(), "t0ken");
+
+    let yml = format!("auth:\n  keys:\n    username: ${{env:MIX_TEST_SECRET_USER}}\n    password: ${{file:{}}}\nsinks:\n  - address: plain\n  - address: {}\n", secret.display(), token);
+    let mut c = Config::default();
+    c.merge(config::File::from_str(&yml, config::FileFormat::Yaml)).unwrap();
+    apply_with(&mut c, &key, env).unwrap();
+    assert_eq!(c.get_str("auth.keys.username").unwrap(), "admin");
+    assert_eq!(c.get_str("auth.keys.password").unwrap(), "s3cret");
+    assert_eq!(c.get_str("sinks[0].address").unwrap(), "plain");
+    assert_eq!(c.get_str("sinks[1].address").unwrap(), "t0ken");
+
+    let mut c = Config::default();
+    c.merge(config::File::from_str("keys:\n  - ${env:MIX_TEST_SECRET_MISSING}\n", config::FileFormat::Yaml)).unwrap();
+    assert_eq!(apply_with(&mut c, &key, env).unwrap_err(), ("keys[0]".to_string(), "环境变量`MIX_TEST_SECRET_MISSING`未设置".to_string()));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
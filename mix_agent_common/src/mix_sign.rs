@@ -0,0 +1,91 @@
+//! 日志提交签名
+//!
+//! 使用 `mix-endpoint-key` 对请求体计算 HMAC-SHA256 签名，签名内容为
+//! `时间戳(毫秒)\n随机数\n请求体`，服务端据此校验请求来源并拒绝重放的请求。
+
+use crate::get_timestamp_millis;
+use hmac::{Hmac, Mac};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::CONTENT_TYPE;
+use sha2::Sha256;
+use uuid::Uuid;
+
+pub const TIMESTAMP_HEADER: &str = "X-Mix-Timestamp";
+pub const NONCE_HEADER: &str = "X-Mix-Nonce";
+pub const SIGNATURE_HEADER: &str = "X-Mix-Signature";
+
+fn mac(key: &str, timestamp: i64, nonce: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC可接受任意长度的密钥");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+    mac.update(b"\n");
+    mac.update(body);
+    mac
+}
+
+/// 计算签名，返回小写十六进制字符串
+pub fn sign(key: &str, timestamp: i64, nonce: &str, body: &[u8]) -> String {
+    hex::encode(mac(key, timestamp, nonce, body).finalize().into_bytes())
+}
+
+/// 校验签名，供服务端或测试使用
+pub fn verify(key: &str, timestamp: i64, nonce: &str, body: &[u8], signature: &str) -> bool {
+    match hex::decode(signature) {
+        Ok(bytes) => mac(key, timestamp, nonce, body).verify_slice(&bytes).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// 构造提交请求，`key`不为空时附加签名头，签名针对实际发送的字节(压缩后的请求体即对压缩数据签名)。
+/// 每次调用生成新的时间戳与随机数，重试时需重新调用
+pub fn signed_post(client: &Client, url: &str, body: &[u8], key: &str) -> RequestBuilder {
+    let request = client.post(url).header(CONTENT_TYPE, "application/json");
+    if key.is_empty() {
+        return request.body(body.to_vec());
+    }
+
+    let timestamp = get_timestamp_millis();
+    let nonce = Uuid::new_v4().to_simple().to_string();
+    let signature = sign(key, timestamp, &nonce, body);
+    request
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(NONCE_HEADER, nonce)
+        .header(SIGNATURE_HEADER, signature)
+        .body(body.to_vec())
+}
+
+#[test]
+fn test_sign_verify() {
+    let signature = sign("1234513abABqe131413", 1610697769239, "abc", b"{}");
+    assert_eq!(signature.len(), 64);
+    assert!(verify("1234513abABqe131413", 1610697769239, "abc", b"{}", &signature));
+    assert!(!verify("1234513abABqe131413", 1610697769240, "abc", b"{}", &signature));
+    assert!(!verify("other", 1610697769239, "abc", b"{}", &signature));
+}
+
+#[test]
+fn test_signed_post_headers() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut request = server.recv().unwrap();
+        let header = |name: &'static str| request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.to_string());
+        let timestamp = header(TIMESTAMP_HEADER).unwrap().parse::<i64>().unwrap();
+        let nonce = header(NONCE_HEADER).unwrap();
+        let signature = header(SIGNATURE_HEADER).unwrap();
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body).unwrap();
+        let valid = verify("1234513abABqe131413", timestamp, &nonce, body.as_bytes(), &signature);
+        request.respond(tiny_http::Response::from_string("ok")).unwrap();
+        (valid, body)
+    });
+
+    let url = format!("http://{}/mix/api/v1/monitor/collect", addr);
+    let res = signed_post(&Client::new(), &url, b"{\"category\":\"cpu\"}", "1234513abABqe131413").send().unwrap();
+    assert!(res.status().is_success());
+
+    let (valid, body) = handle.join().unwrap();
+    assert!(valid);
+    assert_eq!(body, "{\"category\":\"cpu\"}");
+}
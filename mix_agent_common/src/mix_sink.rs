@@ -0,0 +1,409 @@
+//! 日志输出目标(sink)
+//!
+//! `post_log` 将每条日志依次写入 `global.yml` 中 `sinks` 配置的全部输出目标，内置：
+//!
+//! * `mix` - 提交至mix端点(默认)，受 `push-log` 控制
+//! * `file` - 追加写入本地json-lines文件
+//! * `stdout` - 输出至标准输出
+//! * `syslog` - 按RFC 5424格式发送至syslog，支持udp、tcp、unix socket
+//! * `otlp` - 将数值字段转换为OpenTelemetry指标，发送至OTLP/HTTP地址
+//! * `influx` - 按InfluxDB line protocol发送，支持udp、http
+//! * `statsd` - 以gauge发送至StatsD
+
+use crate::mix_config::get_current_dir;
+use crate::mix_influx::InfluxSink;
+use crate::mix_metrics::PrometheusSink;
+use crate::mix_otlp::OtlpSink;
+use crate::mix_statsd::StatsdSink;
+use crate::{deliver, get_local_ip, mix_batch, GlobalConfig, HTTP_CLIENT};
+use chrono::{Local, TimeZone};
+use lazy_static::lazy_static;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 写入输出目标的一条日志
+pub struct Record<'a> {
+    pub agent_name: &'a str,
+    pub category: &'a str,
+    pub level: &'a str,
+    pub time: i64,
+    ///日志序列化后的json
+    pub body: &'a str,
+}
+
+impl<'a> Record<'a> {
+    pub fn value(&self) -> serde_json::Value {
+        serde_json::from_str(self.body).unwrap_or(serde_json::Value::Null)
+    }
+}
+
+/// 输出目标可能被多个探针线程同时写入，需要可变状态的输出目标自行加锁
+pub trait Sink: Send + Sync {
+    fn name(&self) -> String;
+
+    fn write(&self, record: &Record) -> io::Result<()>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct SinkConfig {
+    ///输出类型：mix、file、stdout、syslog、otlp、influx、statsd
+    #[serde(rename = "type")]
+    pub kind: String,
+    ///file：文件路径，相对路径基于程序目录
+    #[serde(default)]
+    pub path: String,
+    ///syslog：传输协议，udp、tcp、unix；influx：udp、http
+    #[serde(default = "default_protocol")]
+    pub protocol: String,
+    ///syslog：地址，udp/tcp为`ip:端口`，unix为socket路径；otlp：OTLP/HTTP地址；influx：udp为`ip:端口`，http为write接口地址；statsd：`ip:端口`
+    #[serde(default)]
+    pub address: String,
+    ///syslog：facility，默认16(local0)
+    #[serde(default = "default_facility")]
+    pub facility: u8,
+    ///otlp：编码，protobuf、json
+    #[serde(default = "default_encoding")]
+    pub encoding: String,
+    ///statsd：指标名前缀
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    ///statsd：是否以DogStatsD扩展格式附加标签
+    #[serde(default)]
+    pub dogstatsd: bool,
+}
+
+fn default_protocol() -> String {
+    "udp".to_string()
+}
+
+fn default_facility() -> u8 {
+    16
+}
+
+fn default_encoding() -> String {
+    "protobuf".to_string()
+}
+
+fn default_prefix() -> String {
+    "mix".to_string()
+}
+
+pub fn default_sinks() -> Vec<SinkConfig> {
+    vec![SinkConfig {
+        kind: "mix".to_string(),
+        path: String::new(),
+        protocol: default_protocol(),
+        address: String::new(),
+        facility: default_facility(),
+        encoding: default_encoding(),
+        prefix: default_prefix(),
+        dogstatsd: false,
+    }]
+}
+
+impl SinkConfig {
+    /// 校验输出类型及必填项，不创建连接
+    pub fn validate(&self) -> Result<(), String> {
+        match self.kind.as_str() {
+            "mix" | "stdout" => Ok(()),
+            "file" if self.path.is_empty() => Err("file输出未配置`path`".to_string()),
+            "file" => Ok(()),
+            "syslog" | "otlp" | "influx" | "statsd" if self.address.is_empty() => Err(format!("{}输出未配置`address`", self.kind)),
+            "syslog" | "otlp" | "influx" | "statsd" => Ok(()),
+            other => Err(format!("不支持的输出类型: {}", other)),
+        }
+    }
+}
+
+/// 根据配置创建输出目标，配置无效时返回错误说明
+pub fn create(config: &SinkConfig, global_config: &GlobalConfig) -> Result<Box<dyn Sink>, String> {
+    match config.kind.as_str() {
+        "mix" => Ok(Box::new(MixSink {
+            global_config: global_config.clone(),
+        })),
+        "file" => {
+            if config.path.is_empty() {
+                return Err("file输出未配置`path`".to_string());
+            }
+            Ok(Box::new(FileSink::new(&config.path)))
+        }
+        "stdout" => Ok(Box::new(StdoutSink)),
+        "syslog" => SyslogSink::new(config).map(|s| Box::new(s) as Box<dyn Sink>),
+        "otlp" => OtlpSink::new(config, global_config.timeout).map(|s| Box::new(s) as Box<dyn Sink>),
+        "influx" => InfluxSink::new(config, global_config.timeout).map(|s| Box::new(s) as Box<dyn Sink>),
+        "statsd" => StatsdSink::new(config).map(|s| Box::new(s) as Box<dyn Sink>),
+        other => Err(format!("不支持的输出类型: {}", other)),
+    }
+}
+
+///创建输出目标时使用的全局配置及输出目标
+type Sinks = Option<(GlobalConfig, Arc<Vec<Box<dyn Sink>>>)>;
+
+lazy_static! {
+    static ref SINKS: Mutex<Sinks> = Mutex::new(None);
+}
+
+/// 写入全部输出目标，全局配置变化时重新创建输出目标
+///
+/// 只在取得输出目标时加锁，写入时不持有锁，避免一个较慢的输出目标(如重试中的mix端点)阻塞其他线程
+pub fn write_all(global_config: &GlobalConfig, record: &Record) {
+    let sinks = match current(global_config) {
+        Some(sinks) => sinks,
+        None => return,
+    };
+    for sink in sinks.iter() {
+        if let Err(e) = sink.write(record) {
+            error!("日志输出失败, 目标:{}, 类型:{}, 错误:{}", sink.name(), record.category, e);
+        }
+    }
+}
+
+fn current(global_config: &GlobalConfig) -> Option<Arc<Vec<Box<dyn Sink>>>> {
+    let mut guard = match SINKS.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("get sinks error:{}", e);
+            return None;
+        }
+    };
+
+    let stale = guard.as_ref().map(|(c, _)| c != global_config).unwrap_or(true);
+    if stale {
+        let mut sinks = vec![];
+        for config in global_config.sinks.iter() {
+            match create(config, global_config) {
+                Ok(sink) => sinks.push(sink),
+                Err(e) => error!("{}", e),
+            }
+        }
+        if global_config.prometheus.enabled {
+            sinks.push(Box::new(PrometheusSink));
+        }
+        *guard = Some((global_config.clone(), Arc::new(sinks)));
+    }
+    guard.as_ref().map(|(_, sinks)| sinks.clone())
+}
+
+/// 提交至mix端点，重试、暂存、批量、签名均在此完成
+pub struct MixSink {
+    global_config: GlobalConfig,
+}
+
+impl Sink for MixSink {
+    fn name(&self) -> String {
+        "mix".to_string()
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        let global_config = &self.global_config;
+        if !global_config.push_log {
+            return Ok(());
+        }
+
+        if global_config.batch.enabled {
+            mix_batch::enqueue(global_config, record.agent_name, record.category, record.body.to_string());
+            return Ok(());
+        }
+
+        deliver(&HTTP_CLIENT, global_config, record.agent_name, record.category, record.body);
+        Ok(())
+    }
+}
+
+/// 追加写入json-lines文件，文件轮转交由logrotate等工具处理
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: &str) -> FileSink {
+        let mut full = PathBuf::from(path);
+        if full.is_relative() {
+            full = Path::new(&get_current_dir()).join(path);
+        }
+        FileSink { path: full }
+    }
+}
+
+impl Sink for FileSink {
+    fn name(&self) -> String {
+        format!("file({})", self.path.display())
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        //一次写入整行，多个线程同时追加时不会交错
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(format!("{}\n", record.body).as_bytes())
+    }
+}
+
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        writeln!(handle, "{}", record.body)
+    }
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    Tcp(Mutex<Option<TcpStream>>),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+}
+
+/// RFC 5424 syslog，tcp按RFC 6587的octet-counting分帧
+pub struct SyslogSink {
+    address: String,
+    facility: u8,
+    hostname: String,
+    transport: Transport,
+}
+
+impl SyslogSink {
+    pub fn new(config: &SinkConfig) -> Result<SyslogSink, String> {
+        if config.address.is_empty() {
+            return Err("syslog输出未配置`address`".to_string());
+        }
+        let transport = match config.protocol.as_str() {
+            "udp" => Transport::Udp(UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?),
+            "tcp" => Transport::Tcp(Mutex::new(None)),
+            #[cfg(unix)]
+            "unix" => Transport::Unix(std::os::unix::net::UnixDatagram::unbound().map_err(|e| e.to_string())?),
+            other => return Err(format!("不支持的syslog协议: {}", other)),
+        };
+        Ok(SyslogSink {
+            address: config.address.clone(),
+            facility: config.facility,
+            hostname: get_local_ip(),
+            transport,
+        })
+    }
+}
+
+/// 按RFC 5424格式化一条syslog消息
+pub fn format_syslog(facility: u8, hostname: &str, record: &Record) -> String {
+    let severity = match record.level {
+        "error" => 3,
+        "warn" => 4,
+        "info" => 6,
+        _ => 7,
+    };
+    let pri = facility as u32 * 8 + severity;
+    let timestamp = Local.timestamp_millis(record.time).to_rfc3339();
+    let nil = |s: &str| if s.is_empty() { "-".to_string() } else { s.replace(' ', "_") };
+    format!("<{}>1 {} {} {} {} {} - {}", pri, timestamp, nil(hostname), nil(record.agent_name), std::process::id(), nil(record.category), record.body)
+}
+
+impl Sink for SyslogSink {
+    fn name(&self) -> String {
+        format!("syslog({})", self.address)
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        let message = format_syslog(self.facility, &self.hostname, record);
+        match &self.transport {
+            Transport::Udp(socket) => socket.send_to(message.as_bytes(), &self.address).map(|_| ()),
+            Transport::Tcp(stream) => {
+                let mut stream = stream.lock().map_err(|e| io::Error::other(e.to_string()))?;
+                if stream.is_none() {
+                    let s = TcpStream::connect(&self.address)?;
+                    s.set_write_timeout(Some(Duration::from_secs(5)))?;
+                    *stream = Some(s);
+                }
+                let frame = format!("{} {}", message.len(), message);
+                let result = stream.as_mut().map(|s| s.write_all(frame.as_bytes())).unwrap_or(Ok(()));
+                if result.is_err() {
+                    //下次写入时重新连接
+                    *stream = None;
+                }
+                result
+            }
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send_to(message.as_bytes(), &self.address).map(|_| ()),
+        }
+    }
+}
+
+#[test]
+fn test_format_syslog() {
+    let record = Record {
+        agent_name: "mix_agent_cpu",
+        category: "cpu",
+        level: "warn",
+        time: 1610697769239,
+        body: "{\"usage\":1.5}",
+    };
+    let message = format_syslog(16, "192.168.30.11", &record);
+    assert!(message.starts_with("<132>1 2021-01-15T"));
+    assert!(message.contains(" 192.168.30.11 mix_agent_cpu "));
+    assert!(message.ends_with(" cpu - {\"usage\":1.5}"));
+}
+
+#[test]
+fn test_syslog_udp_and_file_sink() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let syslog = SinkConfig {
+        kind: "syslog".to_string(),
+        address: server.local_addr().unwrap().to_string(),
+        ..default_sinks().remove(0)
+    };
+    let path = std::env::temp_dir().join(format!("mix_sink_{}.jsonl", uuid::Uuid::new_v4()));
+    let file = SinkConfig {
+        kind: "file".to_string(),
+        path: path.to_str().unwrap().to_string(),
+        ..default_sinks().remove(0)
+    };
+
+    let global_config = GlobalConfig::default();
+    let record = Record {
+        agent_name: "mix_agent_memory",
+        category: "memory",
+        level: "info",
+        time: 1610697769239,
+        body: "{\"usage\":40.1}",
+    };
+    for config in [syslog, file].iter() {
+        create(config, &global_config).unwrap().write(&record).unwrap();
+    }
+
+    let mut buf = [0u8; 1024];
+    let n = server.recv(&mut buf).unwrap();
+    let message = String::from_utf8_lossy(&buf[..n]);
+    assert!(message.starts_with("<134>1 "));
+    assert!(message.ends_with("{\"usage\":40.1}"));
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "{\"usage\":40.1}\n");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_sinks_config() {
+    use config::{Config, File, FileFormat};
+
+    let yaml = "customer-id: c\nproject-id: p\nmix-endpoint: http://mix.wushiai.com\nmix-endpoint-key: k\nsinks:\n  - type: mix\n  - type: syslog\n    protocol: tcp\n    address: 127.0.0.1:601\n";
+    let mut c = Config::default();
+    c.merge(File::from_str(yaml, FileFormat::Yaml)).unwrap();
+    let global_config = c.try_into::<GlobalConfig>().unwrap();
+    assert_eq!(global_config.sinks.len(), 2);
+    assert_eq!(global_config.sinks[1].kind, "syslog");
+    assert_eq!(global_config.sinks[1].protocol, "tcp");
+    assert_eq!(global_config.sinks[1].facility, 16);
+}
@@ -0,0 +1,330 @@
+//! 日志暂存(spool)
+//!
+//! mix端点不可达时，将未能提交的日志按顺序写入 `<程序目录>/spool/<探针名称>.jsonl`，
+//! 端点恢复后按写入顺序补发。暂存文件受 `spool.max-size`、`spool.max-age` 限制，超出时优先淘汰最旧的日志。
+//!
+//! 同一探针的暂存文件可能被采集线程、调度日志线程及批量提交线程同时写入，追加、淘汰及补发均按文件加锁。
+
+use crate::mix_config::get_current_dir;
+use crate::mix_retry::Delivery;
+use crate::get_timestamp_millis;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    static ref LOCKS: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+/// 暂存文件对应的锁，同一文件的多个`Spool`共用
+fn lock_of(path: &Path) -> Arc<Mutex<()>> {
+    let mut locks = LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    locks.entry(path.to_path_buf()).or_default().clone()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct SpoolConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    ///暂存文件最大字节数
+    #[serde(default = "default_max_size")]
+    pub max_size: u64,
+    ///暂存日志最长保留时间，秒
+    #[serde(default = "default_max_age")]
+    pub max_age: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+///默认10mb
+fn default_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+///默认保留7天
+fn default_max_age() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        SpoolConfig {
+            enabled: default_enabled(),
+            max_size: default_max_size(),
+            max_age: default_max_age(),
+        }
+    }
+}
+
+/// 暂存的一条日志，`body`为提交给端点的原始json
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpoolEntry {
+    pub time: i64,
+    pub category: String,
+    pub body: String,
+}
+
+pub struct Spool {
+    path: PathBuf,
+    config: SpoolConfig,
+}
+
+impl Spool {
+    /// 打开探针对应的暂存文件，文件在首次写入时创建
+    pub fn open(agent_name: &str, config: &SpoolConfig) -> Spool {
+        let mut path = PathBuf::from(get_current_dir());
+        path.push("spool");
+        path.push(format!("{}.jsonl", agent_name));
+        Spool::with_path(path, config)
+    }
+
+    pub fn with_path<P: AsRef<Path>>(path: P, config: &SpoolConfig) -> Spool {
+        Spool {
+            path: path.as_ref().to_path_buf(),
+            config: config.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        fs::metadata(&self.path).map(|m| m.len() == 0).unwrap_or(true)
+    }
+
+    /// 追加一条日志，追加后按时间、大小限制淘汰最旧的日志
+    pub fn push(&self, category: &str, body: &str) -> io::Result<()> {
+        let entry = SpoolEntry {
+            time: get_timestamp_millis(),
+            category: category.to_string(),
+            body: body.to_string(),
+        };
+        let line = serde_json::to_string(&entry)?;
+
+        let lock = lock_of(&self.path);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        drop(file);
+
+        //只有超出限制时才读取全部日志淘汰，平时只看文件大小及第一条的时间
+        let size = fs::metadata(&self.path)?.len();
+        let oldest_allowed = get_timestamp_millis() - self.config.max_age as i64 * 1000;
+        let expired = self.first()?.map(|e| e.time < oldest_allowed).unwrap_or(false);
+        if size > self.config.max_size || expired {
+            self.write(&self.evict(self.entries()?, oldest_allowed))?;
+        }
+        Ok(())
+    }
+
+    /// 按写入顺序补发暂存的日志，每次最多`limit`条，返回送达(含被拒绝而丢弃)的条数
+    ///
+    /// 补发期间持有锁，其他线程的追加等待补发结束，避免改写文件时丢失补发期间追加的日志或重复补发
+    pub fn replay<F: FnMut(&SpoolEntry) -> Delivery>(&self, limit: usize, mut send: F) -> io::Result<usize> {
+        let lock = lock_of(&self.path);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = self.entries()?;
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        let mut done = 0;
+        for entry in entries.iter().take(limit) {
+            match send(entry) {
+                Delivery::Delivered => done += 1,
+                Delivery::Rejected => {
+                    warn!("暂存日志被拒绝，已丢弃, 类型:{}", entry.category);
+                    done += 1;
+                }
+                Delivery::Unavailable => break,
+            }
+        }
+
+        self.write(&entries[done..])?;
+        Ok(done)
+    }
+
+    pub fn entries(&self) -> io::Result<Vec<SpoolEntry>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<SpoolEntry>(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => error!("暂存日志解析失败，已跳过: {}", e),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// 最早写入的一条，只读取第一行
+    fn first(&self) -> io::Result<Option<SpoolEntry>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<SpoolEntry>(&line) {
+                Ok(entry) => return Ok(Some(entry)),
+                Err(e) => error!("暂存日志解析失败，已跳过: {}", e),
+            }
+        }
+        Ok(None)
+    }
+
+    fn evict(&self, entries: Vec<SpoolEntry>, oldest_allowed: i64) -> Vec<SpoolEntry> {
+        let mut kept: Vec<SpoolEntry> = entries.into_iter().filter(|e| e.time >= oldest_allowed).collect();
+        let mut size: u64 = kept.iter().map(entry_size).sum();
+        let mut evicted = 0;
+        while size > self.config.max_size && !kept.is_empty() {
+            size -= entry_size(&kept.remove(0));
+            evicted += 1;
+        }
+        if evicted > 0 {
+            info!("暂存日志超出大小限制，已淘汰最旧的{}条", evicted);
+        }
+        kept
+    }
+
+    /// 先写临时文件再替换，避免中途退出导致暂存文件损坏
+    fn write(&self, entries: &[SpoolEntry]) -> io::Result<()> {
+        if entries.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        let tmp = self.path.with_extension("jsonl.tmp");
+        let mut file = fs::File::create(&tmp)?;
+        for entry in entries {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+fn entry_size(entry: &SpoolEntry) -> u64 {
+    serde_json::to_string(entry).map(|s| s.len() as u64 + 1).unwrap_or(0)
+}
+
+#[test]
+fn test_spool_replay_in_order() {
+    let path = std::env::temp_dir().join(format!("mix_spool_{}.jsonl", uuid::Uuid::new_v4()));
+    let spool = Spool::with_path(&path, &SpoolConfig::default());
+    spool.push("cpu", "1").unwrap();
+    spool.push("cpu", "2").unwrap();
+    spool.push("cpu", "3").unwrap();
+
+    let mut sent = vec![];
+    let done = spool
+        .replay(10, |e| {
+            if e.body == "3" {
+                return Delivery::Unavailable;
+            }
+            sent.push(e.body.clone());
+            Delivery::Delivered
+        })
+        .unwrap();
+    assert_eq!(done, 2);
+    assert_eq!(sent, vec!["1", "2"]);
+    assert_eq!(spool.entries().unwrap().len(), 1);
+
+    spool.push("cpu", "4").unwrap();
+    spool.push("cpu", "5").unwrap();
+    let mut sent = vec![];
+    let done = spool
+        .replay(2, |e| {
+            sent.push(e.body.clone());
+            Delivery::Delivered
+        })
+        .unwrap();
+    assert_eq!((done, sent), (2, vec!["3".to_string(), "4".to_string()]));
+    assert_eq!(spool.first().unwrap().unwrap().body, "5");
+
+    spool.replay(10, |_| Delivery::Delivered).unwrap();
+    assert!(spool.is_empty());
+    assert_eq!(spool.first().unwrap(), None);
+}
+
+#[test]
+fn test_spool_evict_oldest() {
+    let path = std::env::temp_dir().join(format!("mix_spool_{}.jsonl", uuid::Uuid::new_v4()));
+    let config = SpoolConfig {
+        max_size: 200,
+        ..Default::default()
+    };
+    let spool = Spool::with_path(&path, &config);
+    for i in 0..10 {
+        spool.push("memory", &format!("{{\"n\":{}}}", i)).unwrap();
+    }
+
+    let entries = spool.entries().unwrap();
+    assert!(fs::metadata(&path).unwrap().len() <= 200);
+    assert_eq!(entries.last().unwrap().body, "{\"n\":9}");
+    assert!(entries.len() < 10);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_spool_push_during_replay() {
+    use std::thread;
+    use std::time::Duration;
+
+    let path = std::env::temp_dir().join(format!("mix_spool_{}.jsonl", uuid::Uuid::new_v4()));
+    let spool = Spool::with_path(&path, &SpoolConfig::default());
+    for i in 0..3 {
+        spool.push("cpu", &i.to_string()).unwrap();
+    }
+
+    //补发较慢时其他线程追加的日志不会丢失，也不会被重复补发
+    let replay = {
+        let path = path.clone();
+        thread::spawn(move || {
+            let mut sent = vec![];
+            Spool::with_path(&path, &SpoolConfig::default())
+                .replay(10, |e| {
+                    thread::sleep(Duration::from_millis(50));
+                    sent.push(e.body.clone());
+                    Delivery::Delivered
+                })
+                .unwrap();
+            sent
+        })
+    };
+    thread::sleep(Duration::from_millis(20));
+    for i in 3..6 {
+        spool.push("cpu", &i.to_string()).unwrap();
+    }
+    let sent = replay.join().unwrap();
+
+    let left: Vec<String> = spool.entries().unwrap().into_iter().map(|e| e.body).collect();
+    let mut all = sent.clone();
+    all.extend(left.iter().cloned());
+    assert_eq!(all, vec!["0", "1", "2", "3", "4", "5"]);
+    assert_eq!(sent, vec!["0", "1", "2"]);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,123 @@
+//! StatsD输出
+//!
+//! `raw_data` 中的数值字段(展开规则同 `mix_metrics`)以gauge发送至 `address` 配置的StatsD udp地址，
+//! 指标名为 `<prefix>.<category>[.<name或序号>].<字段名>`。开启 `dogstatsd` 后，`identity`、`tags`
+//! 以DogStatsD扩展格式(`|#key:value`)附加，否则只包含指标名。
+
+use crate::mix_influx::{pack, MAX_DATAGRAM};
+use crate::mix_metrics::{field_name, flatten};
+use crate::mix_sink::{Record, Sink, SinkConfig};
+use serde_json::Value;
+use std::io;
+use std::net::UdpSocket;
+
+fn bucket(value: &str) -> String {
+    let result: String = value.chars().map(|c| if c.is_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect();
+    result.trim_matches('_').to_string()
+}
+
+/// 将一条日志转换为StatsD gauge
+pub fn to_gauges(log: &Value, prefix: &str, dogstatsd: bool) -> Vec<String> {
+    let category = log.get("category").and_then(|v| v.as_str()).unwrap_or_default();
+
+    flatten(log)
+        .into_iter()
+        .map(|sample| {
+            let mut name = vec![prefix.to_string(), bucket(category)];
+            let mut tags = vec![];
+            for (k, v) in sample.labels.iter() {
+                if k == "name" || k == "index" {
+                    let element = bucket(v);
+                    name.push(if element.is_empty() { "root".to_string() } else { element });
+                } else if !v.is_empty() {
+                    tags.push(format!("{}:{}", k, v.replace([',', '|', '#'], "_")));
+                }
+            }
+            name.push(field_name(category, &sample.name));
+            name.retain(|s| !s.is_empty());
+
+            let mut gauge = format!("{}:{}|g", name.join("."), sample.value);
+            if dogstatsd && !tags.is_empty() {
+                gauge.push_str("|#");
+                gauge.push_str(&tags.join(","));
+            }
+            gauge
+        })
+        .collect()
+}
+
+pub struct StatsdSink {
+    address: String,
+    prefix: String,
+    dogstatsd: bool,
+    socket: UdpSocket,
+}
+
+impl StatsdSink {
+    pub fn new(config: &SinkConfig) -> Result<StatsdSink, String> {
+        if config.address.is_empty() {
+            return Err("statsd输出未配置`address`".to_string());
+        }
+        Ok(StatsdSink {
+            address: config.address.clone(),
+            prefix: config.prefix.clone(),
+            dogstatsd: config.dogstatsd,
+            socket: UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+impl Sink for StatsdSink {
+    fn name(&self) -> String {
+        format!("statsd({})", self.address)
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        let gauges = to_gauges(&record.value(), &self.prefix, self.dogstatsd);
+        for packet in pack(&gauges, MAX_DATAGRAM) {
+            self.socket.send_to(packet.as_bytes(), &self.address)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_to_gauges() {
+    let log: Value = serde_json::from_str(
+        r#"{
+            "identity": {"customer_id": "460001", "project_id": "P12342", "target_ip": "192.168.30.11"},
+            "tags": ["agent-desc|磁盘使用情况"],
+            "category": "disk",
+            "raw_data": [{"usage": 40.5, "name": "/"}, {"usage": 10, "name": "/data"}]
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(to_gauges(&log, "mix", false), vec!["mix.disk.root.usage:40.5|g", "mix.disk.data.usage:10|g"]);
+    assert_eq!(to_gauges(&log, "mix", true)[0], "mix.disk.root.usage:40.5|g|#customer_id:460001,project_id:P12342,target_ip:192.168.30.11,agent_desc:磁盘使用情况");
+}
+
+#[test]
+fn test_statsd_udp() {
+    use std::time::Duration;
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let config = SinkConfig {
+        kind: "statsd".to_string(),
+        address: server.local_addr().unwrap().to_string(),
+        ..crate::mix_sink::default_sinks().remove(0)
+    };
+    let record = Record {
+        agent_name: "mix_agent_memory",
+        category: "memory",
+        level: "info",
+        time: 1610697769239,
+        body: r#"{"identity": {"customer_id": "c", "project_id": "p", "target_ip": "t"}, "tags": [], "category": "memory", "raw_data": {"usage": 41.5, "total": 8}}"#,
+    };
+    StatsdSink::new(&config).unwrap().write(&record).unwrap();
+
+    let mut buf = [0u8; 1500];
+    let n = server.recv(&mut buf).unwrap();
+    assert_eq!(String::from_utf8_lossy(&buf[..n]), "mix.memory.total:8|g\nmix.memory.usage:41.5|g");
+}
@@ -1,14 +1,40 @@
 use log::{info};
-use mix_agent_common::mix_config::{init_logger, MixConfig};
-use mix_agent_common::{init_log, mix_config, post_log, GlobalConfig, LogLevel, Monitor};
+use mix_agent_common::mix_collector::{CollectError, Collector};
+use mix_agent_common::mix_config::{init_logger, AgentConfig, MixConfig};
+use mix_agent_common::mix_host::Host;
+use mix_agent_common::mix_rollup::{rollup, Rollup, Window};
+use mix_agent_common::{LogLevel, Monitor};
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use sysinfo::{ProcessorExt, System, SystemExt};
 
+pub mod procfs;
+pub mod sysfs;
+
+use procfs::{CpuTimes, Load, Stat};
+use sysfs::{Frequency, Temperature};
+
 #[derive(Default, Deserialize, Serialize, Debug)]
 pub struct Cpu {
     time: i64,
     usage: f32,
+    ///逻辑核数
+    cores: usize,
+    ///各核的使用率，按cpu0、cpu1...的顺序
+    core_usage: Vec<f32>,
+    ///自上次采集以来的cpu时间分布，仅linux，首次采集时为null
+    times: Option<CpuTimes>,
+    ///平均负载、任务数、每秒上下文切换及中断次数，仅linux
+    load: Option<Load>,
+    ///各核频率，读取不到时不提交
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    frequencies: Vec<Frequency>,
+    ///温度，读取不到时不提交
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    temperatures: Vec<Temperature>,
 }
 
 impl Cpu {
@@ -20,20 +46,47 @@ impl Cpu {
     }
 }
 
+/// 一批采集结果及使用率的统计
+#[derive(Serialize, Debug)]
+pub struct CpuBatch {
+    samples: Vec<Cpu>,
+    usage: Rollup,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct CpuAgentConfig {
     #[serde(default = "default_cron")]
     cron: String,
+    #[serde(default)]
+    timezone: String,
+    ///累计多少次采集后一起提交
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+    ///距本批第一次采集超过此秒数时不足`batch-size`也提交，0为不限
+    #[serde(default = "default_flush_interval")]
+    flush_interval: u64,
 }
 
 impl Default for CpuAgentConfig {
     fn default() -> Self {
         CpuAgentConfig {
             cron: default_cron(),
+            timezone: String::new(),
+            batch_size: default_batch_size(),
+            flush_interval: default_flush_interval(),
         }
     }
 }
 
+fn default_batch_size() -> usize {
+    10
+}
+
+fn default_flush_interval() -> u64 {
+    60
+}
+
 ///默认每5秒执行一次
 fn default_cron() -> String {
     "*/5 * * * * ?".to_string()
@@ -45,37 +98,121 @@ impl MixConfig for CpuAgentConfig {
     }
 }
 
+impl AgentConfig for CpuAgentConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+
+    fn validate(&self) -> Result<(), (String, String)> {
+        if self.batch_size == 0 {
+            return Err(("batch-size".to_string(), "必须大于0".to_string()));
+        }
+        Ok(())
+    }
+}
+
 const AGENT_NAME: &str = "mix_agent_cpu";
 impl Monitor for Cpu {
     fn collect(&self) {
-        let mut sys = System::new_all();
-        let global_config = mix_config::load::<GlobalConfig>("global");
-        let agent_config = mix_config::load::<CpuAgentConfig>(AGENT_NAME);
-        info!("{:?}", global_config);
-        info!("{:?}", agent_config);
-        let mut result: Vec<Cpu> = vec![];
-        Self::begin(&agent_config.cron, || {
-            //sys.refresh_all();
-            sys.refresh_cpu();
-            let used_cpu = sys.global_processor_info().cpu_usage();
-            let cpu = Cpu {
-                time: chrono::offset::Local::now().timestamp_millis(),
-                usage: used_cpu,
-            };
-
-            #[cfg(debug_assertions)]
-            println!("{:?}", cpu);
-
-            result.push(cpu);
-
-            if result.len() >= 10 {
-                let mut tags = vec![];
-                tags.push("agent-desc|cpu使用率".to_owned());
-                let log = init_log("cpu", "", LogLevel::Warn, Box::new(tags), &result, AGENT_NAME);
-                post_log(&log);
-
-                result.clear();
-            }
-        });
+        let mut host = Host::new();
+        Cpu::register(&mut host, Arc::new(Mutex::new(System::new_all())));
+        host.run();
+    }
+}
+
+impl Cpu {
+    /// 注册到探针宿主，`sys`可由多个探针共用
+    pub fn register(host: &mut Host, sys: Arc<Mutex<System>>) {
+        host.add_collector(CpuCollector::new(sys));
+    }
+}
+
+pub struct CpuCollector {
+    sys: Arc<Mutex<System>>,
+    ///上次读取/proc/stat的时间及内容
+    last_stat: Option<(Instant, Stat)>,
+    window: Window<Cpu>,
+}
+
+impl CpuCollector {
+    pub fn new(sys: Arc<Mutex<System>>) -> CpuCollector {
+        CpuCollector {
+            sys,
+            last_stat: None,
+            window: Window::new(),
+        }
+    }
+
+    /// 读取/proc/stat及/proc/loadavg，与上次读取的差值计算时间分布及每秒次数
+    fn read_procfs(&mut self) -> (Option<CpuTimes>, Option<Load>) {
+        let stat = match procfs::read_stat() {
+            Ok(stat) => stat,
+            Err(_) => return (None, None),
+        };
+        let now = Instant::now();
+        let before = self.last_stat.as_ref().map(|(at, last)| (last, now - *at));
+        let times = before.and_then(|(last, _)| stat.total.percent_since(&last.total));
+        let load = procfs::read_loadavg().ok().map(|loadavg| Load::new(loadavg, &stat, before));
+        self.last_stat = Some((now, stat));
+        (times, load)
+    }
+}
+
+impl Collector for CpuCollector {
+    type Config = CpuAgentConfig;
+    type Output = Option<CpuBatch>;
+
+    fn agent_name(&self) -> &str {
+        AGENT_NAME
+    }
+
+    fn category(&self) -> &str {
+        "cpu"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|cpu使用率".to_owned()]
+    }
+
+    fn level(&self) -> LogLevel {
+        LogLevel::Warn
+    }
+
+    //累计`batch-size`次采集或超过`flush-interval`后连同统计值一起提交
+    fn sample(&mut self, config: &CpuAgentConfig) -> Result<Option<CpuBatch>, CollectError> {
+        let mut sys = self.sys.lock().unwrap();
+        //sys.refresh_all();
+        sys.refresh_cpu();
+        let used_cpu = sys.global_processor_info().cpu_usage();
+        let core_usage: Vec<f32> = sys.processors().iter().map(|p| p.cpu_usage()).collect();
+        drop(sys);
+        let (times, load) = self.read_procfs();
+        let cpu = Cpu {
+            time: chrono::offset::Local::now().timestamp_millis(),
+            usage: used_cpu,
+            cores: core_usage.len(),
+            core_usage,
+            times,
+            load,
+            frequencies: sysfs::read_frequencies(Path::new(sysfs::SYSFS)),
+            temperatures: sysfs::read_temperatures(Path::new(sysfs::SYSFS)),
+        };
+
+        #[cfg(debug_assertions)]
+        println!("{:?}", cpu);
+
+        let samples = match self.window.push(cpu, config.batch_size, Duration::from_secs(config.flush_interval)) {
+            Some(samples) => samples,
+            None => return Ok(None),
+        };
+        let usage: Vec<f64> = samples.iter().map(|c| c.usage as f64).collect();
+        Ok(Some(CpuBatch {
+            usage: rollup(&usage).unwrap_or_default(),
+            samples,
+        }))
     }
 }
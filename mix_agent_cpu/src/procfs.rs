@@ -0,0 +1,212 @@
+//! 读取/proc下的cpu统计，仅linux可用，其他系统读取失败时不提交相应字段
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+/// /proc/stat中cpu行的累计时间，单位为USER_HZ(通常为10毫秒)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CpuTicks {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: u64,
+    pub irq: u64,
+    pub softirq: u64,
+    pub steal: u64,
+}
+
+impl CpuTicks {
+    /// 解析`cpu  4705 356 584 3699176 23060 0 277 0 0 0`中cpu名称之后的部分，旧内核缺少的列按0处理
+    fn parse(fields: &[&str]) -> CpuTicks {
+        let field = |i: usize| fields.get(i).and_then(|v| v.parse().ok()).unwrap_or(0);
+        CpuTicks {
+            user: field(0),
+            nice: field(1),
+            system: field(2),
+            idle: field(3),
+            iowait: field(4),
+            irq: field(5),
+            softirq: field(6),
+            steal: field(7),
+        }
+    }
+
+    //guest已计入user，不重复累加
+    fn total(&self) -> u64 {
+        self.user + self.nice + self.system + self.idle + self.iowait + self.irq + self.softirq + self.steal
+    }
+
+    /// 自`before`以来各项时间的占比，计数器回绕或间隔为0时返回None
+    pub fn percent_since(&self, before: &CpuTicks) -> Option<CpuTimes> {
+        let total = self.total().checked_sub(before.total()).filter(|t| *t > 0)? as f32;
+        let percent = |now: u64, before: u64| now.saturating_sub(before) as f32 * 100.0 / total;
+        Some(CpuTimes {
+            user: percent(self.user, before.user),
+            nice: percent(self.nice, before.nice),
+            system: percent(self.system, before.system),
+            idle: percent(self.idle, before.idle),
+            iowait: percent(self.iowait, before.iowait),
+            irq: percent(self.irq, before.irq),
+            softirq: percent(self.softirq, before.softirq),
+            steal: percent(self.steal, before.steal),
+        })
+    }
+}
+
+/// cpu时间分布，百分比。steal较高说明虚拟机被同一宿主机上的其他虚拟机抢占
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct CpuTimes {
+    pub user: f32,
+    pub nice: f32,
+    pub system: f32,
+    pub idle: f32,
+    pub iowait: f32,
+    pub irq: f32,
+    pub softirq: f32,
+    pub steal: f32,
+}
+
+/// /proc/stat的内容
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stat {
+    ///全部核的合计
+    pub total: CpuTicks,
+    ///按cpu0、cpu1...的顺序
+    pub cores: Vec<CpuTicks>,
+    ///启动以来的上下文切换次数
+    pub ctxt: u64,
+    ///启动以来的中断次数
+    pub intr: u64,
+    pub procs_running: u64,
+    pub procs_blocked: u64,
+}
+
+pub fn parse_stat(content: &str) -> Stat {
+    let mut stat = Stat::default();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.first() {
+            Some(&"cpu") => stat.total = CpuTicks::parse(&fields[1..]),
+            Some(name) if name.starts_with("cpu") => stat.cores.push(CpuTicks::parse(&fields[1..])),
+            Some(name) => {
+                let value = fields.get(1).and_then(|v| v.parse().ok()).unwrap_or(0);
+                match *name {
+                    "ctxt" => stat.ctxt = value,
+                    //第一列为合计，其后为各中断号
+                    "intr" => stat.intr = value,
+                    "procs_running" => stat.procs_running = value,
+                    "procs_blocked" => stat.procs_blocked = value,
+                    _ => {}
+                }
+            }
+            None => {}
+        }
+    }
+    stat
+}
+
+pub fn read_stat() -> io::Result<Stat> {
+    fs::read_to_string("/proc/stat").map(|content| parse_stat(&content))
+}
+
+/// 平均负载及任务数，每秒次数由相邻两次采集的差值计算，首次采集时为null
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Load {
+    ///1、5、15分钟平均负载
+    pub load1: f32,
+    pub load5: f32,
+    pub load15: f32,
+    ///可运行(运行中及等待cpu)的任务数
+    pub procs_running: u64,
+    ///等待io而阻塞的任务数
+    pub procs_blocked: u64,
+    ///每秒上下文切换次数
+    pub ctxt_per_sec: Option<f32>,
+    ///每秒中断次数
+    pub intr_per_sec: Option<f32>,
+}
+
+impl Load {
+    /// `before`为上次读取的/proc/stat及距今的时间
+    pub fn new(loadavg: [f32; 3], stat: &Stat, before: Option<(&Stat, Duration)>) -> Load {
+        let per_sec = |now: u64, before: u64, elapsed: Duration| match elapsed.as_secs_f32() {
+            secs if secs > 0.0 => now.checked_sub(before).map(|d| d as f32 / secs),
+            _ => None,
+        };
+        Load {
+            load1: loadavg[0],
+            load5: loadavg[1],
+            load15: loadavg[2],
+            procs_running: stat.procs_running,
+            procs_blocked: stat.procs_blocked,
+            ctxt_per_sec: before.and_then(|(b, elapsed)| per_sec(stat.ctxt, b.ctxt, elapsed)),
+            intr_per_sec: before.and_then(|(b, elapsed)| per_sec(stat.intr, b.intr, elapsed)),
+        }
+    }
+}
+
+/// 解析`0.20 0.18 0.12 1/80 11206`中的1、5、15分钟平均负载
+pub fn parse_loadavg(content: &str) -> Option<[f32; 3]> {
+    let mut fields = content.split_whitespace().map(|v| v.parse::<f32>().ok());
+    Some([fields.next()??, fields.next()??, fields.next()??])
+}
+
+pub fn read_loadavg() -> io::Result<[f32; 3]> {
+    let content = fs::read_to_string("/proc/loadavg")?;
+    parse_loadavg(&content).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("无法解析/proc/loadavg: {}", content.trim())))
+}
+
+#[test]
+fn test_parse_stat() {
+    let content = "cpu  100 10 50 800 20 5 5 10 0 0\n\
+                   cpu0 60 5 25 390 10 3 2 5 0 0\n\
+                   cpu1 40 5 25 410 10 2 3 5 0 0\n\
+                   intr 12345 0 0\n\
+                   ctxt 67890\n";
+    let stat = parse_stat(content);
+    assert_eq!(stat.cores.len(), 2);
+    assert_eq!(stat.total.idle, 800);
+    assert_eq!(stat.cores[1].softirq, 3);
+
+    let later = CpuTicks {
+        user: 150,
+        system: 70,
+        idle: 910,
+        steal: 30,
+        ..stat.total
+    };
+    let times = later.percent_since(&stat.total).unwrap();
+    assert_eq!(times.user, 25.0);
+    assert_eq!(times.system, 10.0);
+    assert_eq!(times.idle, 55.0);
+    assert_eq!(times.steal, 10.0);
+    assert_eq!(times.iowait, 0.0);
+    assert_eq!(stat.total.percent_since(&stat.total), None);
+
+    //旧内核没有steal列
+    assert_eq!(parse_stat("cpu 1 2 3 4\n").total.steal, 0);
+}
+
+#[test]
+fn test_load() {
+    let before = parse_stat("cpu 1 2 3 4\nintr 1000 10 20\nctxt 5000\nprocs_running 3\nprocs_blocked 0\n");
+    let stat = parse_stat("cpu 1 2 3 4\nintr 3000 10 20\nctxt 6000\nprocs_running 9\nprocs_blocked 2\n");
+    assert_eq!((stat.intr, stat.ctxt, stat.procs_running, stat.procs_blocked), (3000, 6000, 9, 2));
+
+    let loadavg = parse_loadavg("0.20 0.18 0.12 1/80 11206\n").unwrap();
+    assert_eq!(loadavg, [0.20, 0.18, 0.12]);
+    assert_eq!(parse_loadavg("0.20 x"), None);
+
+    let load = Load::new(loadavg, &stat, Some((&before, Duration::from_secs(2))));
+    assert_eq!(load.load5, 0.18);
+    assert_eq!((load.procs_running, load.procs_blocked), (9, 2));
+    assert_eq!(load.ctxt_per_sec, Some(500.0));
+    assert_eq!(load.intr_per_sec, Some(1000.0));
+
+    assert_eq!(Load::new(loadavg, &stat, None).ctxt_per_sec, None);
+    //计数器重置时不计算
+    assert_eq!(Load::new(loadavg, &before, Some((&stat, Duration::from_secs(2)))).intr_per_sec, None);
+}
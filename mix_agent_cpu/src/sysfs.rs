@@ -0,0 +1,178 @@
+//! 读取/sys下的cpu频率及温度，仅linux可用，不存在的项不提交
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SYSFS: &str = "/sys";
+
+/// 一个核的频率，MHz
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Frequency {
+    pub cpu: usize,
+    pub cur_mhz: Option<u64>,
+    ///硬件支持的最低、最高频率，当前频率长时间明显低于最高频率时可能因过热降频
+    pub min_mhz: Option<u64>,
+    pub max_mhz: Option<u64>,
+}
+
+/// 一个温度传感器的读数
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Temperature {
+    ///hwmon的name或thermal_zone的type，如coretemp、x86_pkg_temp
+    pub sensor: String,
+    ///hwmon的temp*_label(如Core 0)，没有时为temp1等，thermal_zone为thermal_zone0等
+    pub label: String,
+    pub celsius: f32,
+}
+
+fn read_string(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+fn read_number(path: &Path) -> Option<i64> {
+    read_string(path)?.parse().ok()
+}
+
+/// `dir`下以`prefix`加数字命名的项，按数字排序
+fn numbered(dir: &Path, prefix: &str) -> Vec<(usize, PathBuf)> {
+    let mut entries: Vec<(usize, PathBuf)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                let n = name.strip_prefix(prefix)?.parse().ok()?;
+                Some((n, e.path()))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    entries.sort_by_key(|(n, _)| *n);
+    entries
+}
+
+/// 读取`root`/devices/system/cpu/cpu*/cpufreq，没有cpufreq(如部分虚拟机)的核不返回
+pub fn read_frequencies(root: &Path) -> Vec<Frequency> {
+    let mhz = |path: PathBuf| read_number(&path).map(|khz| khz as u64 / 1000);
+    numbered(&root.join("devices/system/cpu"), "cpu")
+        .into_iter()
+        .filter_map(|(cpu, dir)| {
+            let freq = dir.join("cpufreq");
+            if !freq.is_dir() {
+                return None;
+            }
+            Some(Frequency {
+                cpu,
+                cur_mhz: mhz(freq.join("scaling_cur_freq")).or_else(|| mhz(freq.join("cpuinfo_cur_freq"))),
+                min_mhz: mhz(freq.join("cpuinfo_min_freq")),
+                max_mhz: mhz(freq.join("cpuinfo_max_freq")),
+            })
+        })
+        .collect()
+}
+
+/// 读取`root`/class/hwmon及`root`/class/thermal下的温度，单位为千分之一摄氏度
+pub fn read_temperatures(root: &Path) -> Vec<Temperature> {
+    let celsius = |path: &Path| read_number(path).map(|milli| milli as f32 / 1000.0);
+    let mut temperatures = vec![];
+    for (_, dir) in numbered(&root.join("class/hwmon"), "hwmon") {
+        let sensor = read_string(&dir.join("name")).unwrap_or_default();
+        let mut inputs: Vec<(usize, String)> = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| {
+                    let name = e.file_name().to_string_lossy().to_string();
+                    let n = name.strip_prefix("temp")?.strip_suffix("_input")?.parse().ok()?;
+                    Some((n, name))
+                })
+                .collect(),
+            Err(_) => vec![],
+        };
+        inputs.sort();
+        for (n, input) in inputs {
+            if let Some(value) = celsius(&dir.join(input)) {
+                temperatures.push(Temperature {
+                    sensor: sensor.clone(),
+                    label: read_string(&dir.join(format!("temp{}_label", n))).unwrap_or_else(|| format!("temp{}", n)),
+                    celsius: value,
+                });
+            }
+        }
+    }
+    for (n, dir) in numbered(&root.join("class/thermal"), "thermal_zone") {
+        if let Some(value) = celsius(&dir.join("temp")) {
+            temperatures.push(Temperature {
+                sensor: read_string(&dir.join("type")).unwrap_or_default(),
+                label: format!("thermal_zone{}", n),
+                celsius: value,
+            });
+        }
+    }
+    temperatures
+}
+
+#[cfg(test)]
+fn fake_sysfs(files: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("mix_sysfs_{}_{:?}", std::process::id(), std::thread::current().id()));
+    let _ = fs::remove_dir_all(&root);
+    for (path, content) in files {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    root
+}
+
+#[test]
+fn test_read_frequencies() {
+    let root = fake_sysfs(&[
+        ("devices/system/cpu/cpu0/cpufreq/scaling_cur_freq", "2400000\n"),
+        ("devices/system/cpu/cpu0/cpufreq/cpuinfo_min_freq", "800000\n"),
+        ("devices/system/cpu/cpu0/cpufreq/cpuinfo_max_freq", "3600000\n"),
+        ("devices/system/cpu/cpu10/cpufreq/cpuinfo_cur_freq", "1200000\n"),
+        ("devices/system/cpu/cpu2/cpufreq/scaling_cur_freq", "3500000\n"),
+        //没有cpufreq的核及非cpu目录
+        ("devices/system/cpu/cpu1/online", "1\n"),
+        ("devices/system/cpu/cpuidle/current_driver", "intel_idle\n"),
+    ]);
+    let frequencies = read_frequencies(&root);
+    assert_eq!(frequencies.iter().map(|f| f.cpu).collect::<Vec<_>>(), vec![0, 2, 10]);
+    assert_eq!(
+        frequencies[0],
+        Frequency {
+            cpu: 0,
+            cur_mhz: Some(2400),
+            min_mhz: Some(800),
+            max_mhz: Some(3600),
+        }
+    );
+    assert_eq!((frequencies[2].cur_mhz, frequencies[2].max_mhz), (Some(1200), None));
+
+    assert!(read_frequencies(&root.join("missing")).is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_read_temperatures() {
+    let root = fake_sysfs(&[
+        ("class/hwmon/hwmon1/name", "coretemp\n"),
+        ("class/hwmon/hwmon1/temp1_input", "45000\n"),
+        ("class/hwmon/hwmon1/temp1_label", "Package id 0\n"),
+        ("class/hwmon/hwmon1/temp2_input", "43500\n"),
+        ("class/hwmon/hwmon1/temp2_max", "100000\n"),
+        ("class/hwmon/hwmon0/name", "acpitz\n"),
+        ("class/hwmon/hwmon0/temp1_input", "\n"),
+        ("class/thermal/thermal_zone0/type", "x86_pkg_temp\n"),
+        ("class/thermal/thermal_zone0/temp", "46000\n"),
+        ("class/thermal/cooling_device0/type", "Processor\n"),
+    ]);
+    let temperatures = read_temperatures(&root);
+    let readings: Vec<(&str, &str, f32)> = temperatures.iter().map(|t| (t.sensor.as_str(), t.label.as_str(), t.celsius)).collect();
+    assert_eq!(
+        readings,
+        vec![("coretemp", "Package id 0", 45.0), ("coretemp", "temp2", 43.5), ("x86_pkg_temp", "thermal_zone0", 46.0)]
+    );
+
+    assert!(read_temperatures(&root.join("missing")).is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -1,7 +1,9 @@
 use chrono::{DateTime, Local};
 use log::info;
-use mix_agent_common::mix_config::{init_logger, MixConfig};
-use mix_agent_common::{get_global_config, init_log, mix_config, post_log, LogLevel, Monitor};
+use mix_agent_common::mix_collector::{CollectError, Collector};
+use mix_agent_common::mix_config::{init_logger, AgentConfig, MixConfig};
+use mix_agent_common::mix_host::Host;
+use mix_agent_common::Monitor;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
@@ -9,8 +11,8 @@ use std::path::Path;
 const AGENT_NAME: &str = "mix_agent_directory";
 
 #[derive(Debug, Serialize)]
-pub struct Summary<'a> {
-    root_path: &'a str,
+pub struct Summary {
+    root_path: String,
     results: Vec<Directory>,
 }
 
@@ -44,17 +46,20 @@ impl Directory {
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "kebab-case")]
-struct AppScanConfig {
+pub struct AppScanConfig {
     #[serde(default)]
     root_path: String,
     #[serde(default = "default_cron")]
     cron: String,
+    #[serde(default)]
+    timezone: String,
 }
 
 impl Default for AppScanConfig {
     fn default() -> Self {
         AppScanConfig {
             cron: default_cron(),
+            timezone: String::new(),
             root_path: "".to_string(),
         }
     }
@@ -71,75 +76,121 @@ impl MixConfig for AppScanConfig {
     }
 }
 
+impl AgentConfig for AppScanConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+}
+
 impl Monitor for Directory {
     fn collect(&self) {
-        let global_config = get_global_config();
-        let agent_config = mix_config::load::<AppScanConfig>(AGENT_NAME);
-        info!("{:?}", global_config);
-        info!("{:?}", agent_config);
-
-        let mut tags = vec![];
-        tags.push("agent-desc|目录信息采集".to_owned());
-        Self::begin(&agent_config.cron, || {
-            let mut summary = Summary {
-                root_path: "",
-                results: vec![],
+        let mut host = Host::new();
+        Directory::register(&mut host);
+        host.run();
+    }
+}
+
+impl Directory {
+    /// 注册到探针宿主
+    pub fn register(host: &mut Host) {
+        host.add_collector(DirectoryCollector);
+    }
+}
+
+pub struct DirectoryCollector;
+
+impl Collector for DirectoryCollector {
+    type Config = AppScanConfig;
+    type Output = Summary;
+
+    fn agent_name(&self) -> &str {
+        AGENT_NAME
+    }
+
+    fn category(&self) -> &str {
+        "directory"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|目录信息采集".to_owned()]
+    }
+
+    fn sample(&mut self, agent_config: &AppScanConfig) -> Result<Summary, CollectError> {
+        let path = Path::new(&agent_config.root_path);
+        if !path.exists() {
+            return Err(CollectError::warn("50001:未配置采集根目录`root-path`"));
+        }
+
+        let paths = Path::read_dir(path).unwrap();
+        let mut results: Vec<Directory> = vec![];
+        for path in paths {
+            let p = path.as_ref().unwrap();
+            let metadata = p.metadata().unwrap();
+
+            if metadata.is_file() {
+                continue;
+            }
+
+            let current_path = p.path().into_os_string().into_string().unwrap();
+
+            let mut created_time = 0;
+            if cfg!(target_os = "macos") || cfg!(target_os = "windows") {
+                let created: DateTime<Local> = metadata.created().unwrap().into();
+                created_time = created.timestamp_millis();
+            }
+
+            let modified: DateTime<Local> = metadata.modified().unwrap().into();
+            let mut app_scan = Directory {
+                path: current_path.clone(),
+                created: created_time,
+                modified: modified.timestamp_millis(),
+                app_name: "".to_string(),
+                app_version: "".to_string(),
+                app_desc: "".to_string(),
+                link_man: "".to_string(),
             };
-            let _content = String::new();
-            let path = Path::new(&agent_config.root_path);
-            if !path.exists() {
-                let log = init_log("agent", "50001:未配置采集根目录`root-path`", LogLevel::Warn, Box::new(tags.clone()), "", AGENT_NAME);
-                post_log(&log);
-            } else {
-                let paths = Path::read_dir(path).unwrap();
-                let mut results: Vec<Directory> = vec![];
-                for path in paths {
-                    let p = path.as_ref().unwrap();
-                    let metadata = p.metadata().unwrap();
-
-                    if metadata.is_file() {
-                        continue;
-                    }
-
-                    let current_path = p.path().into_os_string().into_string().unwrap();
-
-                    let mut created_time = 0;
-                    if cfg!(target_os = "macos") || cfg!(target_os = "windows") {
-                        let created: DateTime<Local> = metadata.created().unwrap().into();
-                        created_time = created.timestamp_millis();
-                    }
-
-                    let modified: DateTime<Local> = metadata.modified().unwrap().into();
-                    let mut app_scan = Directory {
-                        path: current_path.clone(),
-                        created: created_time,
-                        modified: modified.timestamp_millis(),
-                        app_name: "".to_string(),
-                        app_version: "".to_string(),
-                        app_desc: "".to_string(),
-                        link_man: "".to_string(),
-                    };
-
-                    let app_info_path = Path::new(current_path.as_str()).join("app_info.yml");
-                    if app_info_path.exists() {
-                        let contents = fs::read_to_string(app_info_path).unwrap();
-                        let app_info: Directory = serde_yaml::from_str::<Directory>(contents.as_str()).unwrap_or(Directory::default());
-
-                        app_scan.app_name = app_info.app_name;
-                        app_scan.app_desc = app_info.app_desc;
-                        app_scan.app_version = app_info.app_version;
-                        app_scan.link_man = app_info.link_man;
-                    }
-
-                    results.push(app_scan);
-                }
-
-                summary.root_path = path.to_str().unwrap();
-                summary.results = results;
-
-                let log = init_log("directory", "", LogLevel::Info, Box::new(tags.clone()), &summary, AGENT_NAME);
-                post_log(&log);
+
+            let app_info_path = Path::new(current_path.as_str()).join("app_info.yml");
+            if app_info_path.exists() {
+                let contents = fs::read_to_string(app_info_path).unwrap();
+                let app_info: Directory = serde_yaml::from_str::<Directory>(contents.as_str()).unwrap_or(Directory::default());
+
+                app_scan.app_name = app_info.app_name;
+                app_scan.app_desc = app_info.app_desc;
+                app_scan.app_version = app_info.app_version;
+                app_scan.link_man = app_info.link_man;
             }
+
+            results.push(app_scan);
+        }
+
+        Ok(Summary {
+            root_path: path.to_str().unwrap().to_string(),
+            results,
         })
     }
 }
+
+#[test]
+fn test_sample() {
+    let root = std::env::temp_dir().join(format!("mix_directory_{}", std::process::id()));
+    fs::create_dir_all(root.join("app")).unwrap();
+    fs::write(root.join("app").join("app_info.yml"), "app_name: 测试应用\napp_version: 1.0.0\n").unwrap();
+    fs::write(root.join("readme.txt"), "").unwrap();
+
+    let mut config = AppScanConfig::default();
+    let mut collector = DirectoryCollector;
+    assert_eq!(collector.sample(&config).unwrap_err().content, "50001:未配置采集根目录`root-path`");
+
+    config.root_path = root.to_string_lossy().to_string();
+    let summary = collector.sample(&config).unwrap();
+    assert_eq!(summary.results.len(), 1);
+    assert_eq!(summary.results[0].app_name, "测试应用");
+    assert_eq!(summary.results[0].app_version, "1.0.0");
+
+    fs::remove_dir_all(&root).unwrap();
+}
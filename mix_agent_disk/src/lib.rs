@@ -1,6 +1,8 @@
 use log::info;
-use mix_agent_common::mix_config::{init_logger, MixConfig};
-use mix_agent_common::{init_log, mix_config, post_log, GlobalConfig, LogLevel, Monitor};
+use mix_agent_common::mix_collector::{CollectError, Collector};
+use mix_agent_common::mix_config::{init_logger, AgentConfig, MixConfig};
+use mix_agent_common::mix_host::Host;
+use mix_agent_common::Monitor;
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Serialize, Debug)]
@@ -25,6 +27,8 @@ impl Disk {
 pub struct DiskAgentConfig {
     #[serde(default = "default_cron")]
     cron: String,
+    #[serde(default)]
+    timezone: String,
     #[serde(default = "default_file_system")]
     file_system: Vec<String>,
 }
@@ -41,6 +45,7 @@ impl Default for DiskAgentConfig {
     fn default() -> Self {
         DiskAgentConfig {
             cron: default_cron(),
+            timezone: String::new(),
             file_system: default_file_system(),
         }
     }
@@ -51,6 +56,16 @@ impl MixConfig for DiskAgentConfig {
         DiskAgentConfig::default()
     }
 }
+
+impl AgentConfig for DiskAgentConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+}
 use systemstat::{Platform, System};
 
 
@@ -60,52 +75,80 @@ const AGENT_NAME: &str = "mix_agent_disk";
 impl Monitor for Disk {
     fn collect(&self) {
         //let mut sys = System::new_all();
-        let global_config = mix_config::load::<GlobalConfig>("global");
-        let agent_config = mix_config::load::<DiskAgentConfig>(AGENT_NAME);
-
-        info!("{:?}", global_config);
-        info!("{:?}", agent_config);
-
-        let sys = System::new();
-
-        Self::begin(&agent_config.cron, || {
-            match sys.mounts() {
-                Ok(mounts) => {
-                    let mut result: Vec<Disk> = vec![];
-                    for mount in mounts.iter() {
-                        //println!("{} ---{}---> {} (available {} of {} {:?})", mount.fs_mounted_from, mount.fs_type, mount.fs_mounted_on, mount.avail.as_u64(), mount.total.as_u64(), mount);
-                        let file_system = &mount.fs_type;
-                        let fstype = &agent_config.file_system;
-                        if !fstype.contains(&file_system.to_lowercase()) {
-                            continue;
-                        }
-
-                        let total_space = mount.total.as_u64();
-                        let available_space = mount.avail.as_u64();
-                        let used_space = mount.total.as_u64() - mount.avail.as_u64();
-
-                        let disk_name = mount.fs_mounted_on.clone();
-                        let current = Disk {
-                            total_space,
-                            available_space,
-                            used_space,
-                            usage: used_space as f32 / total_space as f32 * 100f32.round(),
-                            file_system: file_system.to_string(),
-                            name: disk_name,
-                        };
-
-                        result.push(current);
-                    }
-
-                    //print!("{:?}", result);
-                    let mut tags = vec![];
-                    tags.push("agent-desc|磁盘使用情况".to_owned());
-                    tags.push("data-unit|字节".to_owned());
-                    let log = init_log("disk", "", LogLevel::Info, Box::new(tags.clone()), &result, AGENT_NAME);
-                    post_log(&log);
-                }
-                Err(x) => println!("\nMounts: error: {}", x),
+        let mut host = Host::new();
+        Disk::register(&mut host);
+        host.run();
+    }
+}
+
+impl Disk {
+    /// 注册到探针宿主
+    pub fn register(host: &mut Host) {
+        host.add_collector(DiskCollector::new());
+    }
+}
+
+pub struct DiskCollector {
+    sys: System,
+}
+
+impl DiskCollector {
+    pub fn new() -> DiskCollector {
+        DiskCollector { sys: System::new() }
+    }
+}
+
+impl Default for DiskCollector {
+    fn default() -> Self {
+        DiskCollector::new()
+    }
+}
+
+impl Collector for DiskCollector {
+    type Config = DiskAgentConfig;
+    type Output = Vec<Disk>;
+
+    fn agent_name(&self) -> &str {
+        AGENT_NAME
+    }
+
+    fn category(&self) -> &str {
+        "disk"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|磁盘使用情况".to_owned(), "data-unit|字节".to_owned()]
+    }
+
+    fn sample(&mut self, agent_config: &DiskAgentConfig) -> Result<Vec<Disk>, CollectError> {
+        let mounts = self.sys.mounts().map_err(|x| CollectError::error(&format!("30001:获取磁盘挂载信息失败: {}", x)))?;
+        let mut result: Vec<Disk> = vec![];
+        for mount in mounts.iter() {
+            //println!("{} ---{}---> {} (available {} of {} {:?})", mount.fs_mounted_from, mount.fs_type, mount.fs_mounted_on, mount.avail.as_u64(), mount.total.as_u64(), mount);
+            let file_system = &mount.fs_type;
+            let fstype = &agent_config.file_system;
+            if !fstype.contains(&file_system.to_lowercase()) {
+                continue;
             }
-        });
+
+            let total_space = mount.total.as_u64();
+            let available_space = mount.avail.as_u64();
+            let used_space = mount.total.as_u64() - mount.avail.as_u64();
+
+            let disk_name = mount.fs_mounted_on.clone();
+            let current = Disk {
+                total_space,
+                available_space,
+                used_space,
+                usage: used_space as f32 / total_space as f32 * 100f32.round(),
+                file_system: file_system.to_string(),
+                name: disk_name,
+            };
+
+            result.push(current);
+        }
+
+        //print!("{:?}", result);
+        Ok(result)
     }
 }
@@ -1,7 +1,10 @@
 use log::info;
-use mix_agent_common::mix_config::{init_logger, MixConfig};
-use mix_agent_common::{init_log, mix_config, post_log, GlobalConfig, LogLevel, Monitor};
+use mix_agent_common::mix_collector::{CollectError, Collector};
+use mix_agent_common::mix_config::{init_logger, AgentConfig, MixConfig};
+use mix_agent_common::mix_host::Host;
+use mix_agent_common::Monitor;
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
 
 use sysinfo::{System, SystemExt};
 
@@ -41,6 +44,8 @@ impl Machine {
 pub struct MachineAgentConfig {
     #[serde(default = "default_cron")]
     cron: String,
+    #[serde(default)]
+    timezone: String,
     machine_name: String,
 }
 
@@ -53,6 +58,7 @@ impl Default for MachineAgentConfig {
     fn default() -> Self {
         MachineAgentConfig {
             cron: default_cron(),
+            timezone: String::new(),
             machine_name: "".to_string(),
         }
     }
@@ -64,42 +70,76 @@ impl MixConfig for MachineAgentConfig {
     }
 }
 
+impl AgentConfig for MachineAgentConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+}
+
 impl Monitor for Machine {
     fn collect(&self) {
-        let sys = System::new_all();
-        let global_config = mix_config::load::<GlobalConfig>("global");
-        let agent_config = mix_config::load::<MachineAgentConfig>(AGENT_NAME);
-
-        info!("{:?}", global_config);
-        info!("{:?}", agent_config);
-
-        let mut tags = vec![];
-        tags.push("agent-desc|服务器信息采集".to_owned());
-
-        Self::begin(&agent_config.cron, || {
-            if agent_config.machine_name.is_empty() {
-                let log = init_log("agent", "40001:未配置服务器名称`machine-name`", LogLevel::Warn, Box::new(tags.clone()), "", AGENT_NAME);
-                post_log(&log);
-            } else {
-                let os_info = os_info::get();
-                let machine = Machine {
-                    os_name: std::env::consts::OS.to_string(),
-                    os_arch: std::env::consts::ARCH.to_string(),
-                    os_family: std::env::consts::FAMILY.to_string(),
-                    os_version: os_info.version().to_string(),
-                    os_edition: os_info.edition().unwrap_or("未知").to_string(),
-                    os_core: sys_info::os_type().unwrap(),
-                    os_core_version: sys_info::os_release().unwrap(),
-                    boot_time: sys.boot_time() * 1000,
-                    up_time: sys.uptime(),
-                    host_name: sys_info::hostname().unwrap(),
-                    ip: local_ipaddress::get().unwrap(),
-                    machine_name: agent_config.machine_name.clone(),
-                };
-
-                let log = init_log("machine", "", LogLevel::Info, Box::new(tags.clone()), &machine, AGENT_NAME);
-                post_log(&log);
-            }
+        let mut host = Host::new();
+        Machine::register(&mut host, Arc::new(Mutex::new(System::new_all())));
+        host.run();
+    }
+}
+
+impl Machine {
+    /// 注册到探针宿主，`sys`可由多个探针共用
+    pub fn register(host: &mut Host, sys: Arc<Mutex<System>>) {
+        host.add_collector(MachineCollector::new(sys));
+    }
+}
+
+pub struct MachineCollector {
+    sys: Arc<Mutex<System>>,
+}
+
+impl MachineCollector {
+    pub fn new(sys: Arc<Mutex<System>>) -> MachineCollector {
+        MachineCollector { sys }
+    }
+}
+
+impl Collector for MachineCollector {
+    type Config = MachineAgentConfig;
+    type Output = Machine;
+
+    fn agent_name(&self) -> &str {
+        AGENT_NAME
+    }
+
+    fn category(&self) -> &str {
+        "machine"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|服务器信息采集".to_owned()]
+    }
+
+    fn sample(&mut self, agent_config: &MachineAgentConfig) -> Result<Machine, CollectError> {
+        if agent_config.machine_name.is_empty() {
+            return Err(CollectError::warn("40001:未配置服务器名称`machine-name`"));
+        }
+        let sys = self.sys.lock().unwrap();
+        let os_info = os_info::get();
+        Ok(Machine {
+            os_name: std::env::consts::OS.to_string(),
+            os_arch: std::env::consts::ARCH.to_string(),
+            os_family: std::env::consts::FAMILY.to_string(),
+            os_version: os_info.version().to_string(),
+            os_edition: os_info.edition().unwrap_or("未知").to_string(),
+            os_core: sys_info::os_type().unwrap(),
+            os_core_version: sys_info::os_release().unwrap(),
+            boot_time: sys.boot_time() * 1000,
+            up_time: sys.uptime(),
+            host_name: sys_info::hostname().unwrap(),
+            ip: local_ipaddress::get().unwrap(),
+            machine_name: agent_config.machine_name.clone(),
         })
     }
 }
@@ -1,9 +1,17 @@
 use log::info;
-use mix_agent_common::mix_config::{init_logger, MixConfig};
-use mix_agent_common::{init_log, mix_config, post_log, GlobalConfig, LogLevel, Monitor};
+use mix_agent_common::mix_collector::{CollectError, Collector};
+use mix_agent_common::mix_config::{init_logger, AgentConfig, MixConfig};
+use mix_agent_common::mix_host::Host;
+use mix_agent_common::Monitor;
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use sysinfo::{System, SystemExt};
 
+pub mod procfs;
+
+use procfs::{MemInfo, Paging, Pressure, VmStat};
+
 #[derive(Debug, Serialize, Default)]
 pub struct Memory {
     total_memory: u64,
@@ -11,6 +19,15 @@ pub struct Memory {
     free_memory: u64,
     used_memory: u64,
     usage: f32,
+    swap_total: u64,
+    swap_used: u64,
+    swap_free: u64,
+    ///buffers、cached及slab，仅linux
+    breakdown: Option<MemInfo>,
+    ///每秒换入换出页数及主缺页次数，仅linux，首次采集时为null
+    paging: Option<Paging>,
+    ///内存压力(PSI)，仅支持PSI的linux
+    pressure: Option<Pressure>,
 }
 
 impl Memory {
@@ -26,6 +43,8 @@ impl Memory {
 pub struct MemoryAgentConfig {
     #[serde(default = "default_cron")]
     cron: String,
+    #[serde(default)]
+    timezone: String,
 }
 
 ///默认每15秒执行一次
@@ -37,6 +56,7 @@ impl Default for MemoryAgentConfig {
     fn default() -> Self {
         MemoryAgentConfig {
             cron: default_cron(),
+            timezone: String::new(),
         }
     }
 }
@@ -47,35 +67,95 @@ impl MixConfig for MemoryAgentConfig {
     }
 }
 
+impl AgentConfig for MemoryAgentConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+}
+
 const AGENT_NAME: &str = "mix_agent_memory";
 impl Monitor for Memory {
     fn collect(&self) {
-        let mut sys = System::new_all();
-        let global_config = mix_config::load::<GlobalConfig>("global");
-        let agent_config = mix_config::load::<MemoryAgentConfig>(AGENT_NAME);
-        info!("{:?}", global_config);
-        info!("{:?}", agent_config);
-        Self::begin(&agent_config.cron, || {
-            sys.refresh_all();
-            let total_memory = sys.total_memory();
-            let available_memory = sys.available_memory(); //.available_memory();
-            let free_memory = sys.free_memory();
-            let used_memory = sys.used_memory();
-            let usage = used_memory as f32 / total_memory as f32 * 100f32;
-
-            let result = Memory {
-                total_memory,
-                available_memory,
-                free_memory,
-                used_memory,
-                usage,
-            };
-
-            let mut tags = vec![];
-            tags.push("agent-desc|内存使用率".to_owned());
-            tags.push("data-unit|千字节".to_owned());
-            let log = init_log("memory", "", LogLevel::Info, Box::new(tags.clone()), &result, AGENT_NAME);
-            post_log(&log);
+        let mut host = Host::new();
+        Memory::register(&mut host, Arc::new(Mutex::new(System::new_all())));
+        host.run();
+    }
+}
+
+impl Memory {
+    /// 注册到探针宿主，`sys`可由多个探针共用
+    pub fn register(host: &mut Host, sys: Arc<Mutex<System>>) {
+        host.add_collector(MemoryCollector::new(sys));
+    }
+}
+
+pub struct MemoryCollector {
+    sys: Arc<Mutex<System>>,
+    ///上次读取/proc/vmstat的时间及内容
+    last_vmstat: Option<(Instant, VmStat)>,
+}
+
+impl MemoryCollector {
+    pub fn new(sys: Arc<Mutex<System>>) -> MemoryCollector {
+        MemoryCollector { sys, last_vmstat: None }
+    }
+
+    /// 读取/proc/vmstat，与上次读取的差值计算每秒次数
+    fn paging(&mut self) -> Option<Paging> {
+        let vmstat = procfs::read_vmstat().ok()?;
+        let now = Instant::now();
+        let paging = self.last_vmstat.as_ref().and_then(|(at, last)| Paging::since(&vmstat, last, now - *at));
+        self.last_vmstat = Some((now, vmstat));
+        paging
+    }
+}
+
+impl Collector for MemoryCollector {
+    type Config = MemoryAgentConfig;
+    type Output = Memory;
+
+    fn agent_name(&self) -> &str {
+        AGENT_NAME
+    }
+
+    fn category(&self) -> &str {
+        "memory"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|内存使用率".to_owned(), "data-unit|千字节".to_owned()]
+    }
+
+    fn sample(&mut self, _: &MemoryAgentConfig) -> Result<Memory, CollectError> {
+        let mut sys = self.sys.lock().unwrap();
+        //共用时只刷新内存信息
+        sys.refresh_memory();
+        let total_memory = sys.total_memory();
+        let available_memory = sys.available_memory(); //.available_memory();
+        let free_memory = sys.free_memory();
+        let used_memory = sys.used_memory();
+        let usage = used_memory as f32 / total_memory as f32 * 100f32;
+        let swap_total = sys.total_swap();
+        let swap_used = sys.used_swap();
+        let swap_free = sys.free_swap();
+        drop(sys);
+
+        Ok(Memory {
+            total_memory,
+            available_memory,
+            free_memory,
+            used_memory,
+            usage,
+            swap_total,
+            swap_used,
+            swap_free,
+            breakdown: procfs::read_meminfo().ok(),
+            paging: self.paging(),
+            pressure: procfs::read_pressure().ok(),
         })
     }
 }
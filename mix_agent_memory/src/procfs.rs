@@ -0,0 +1,183 @@
+//! 读取/proc下的内存统计，仅linux可用，其他系统读取失败时相应字段为null
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+/// /proc/meminfo中的缓存及内核占用，千字节
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct MemInfo {
+    pub buffers: u64,
+    pub cached: u64,
+    pub slab: u64,
+    ///slab中可回收的部分
+    pub slab_reclaimable: u64,
+}
+
+/// 解析`Buffers:          123456 kB`形式的行，缺少的项按0处理
+pub fn parse_meminfo(content: &str) -> MemInfo {
+    let values: HashMap<&str, u64> = content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            Some((key.trim(), value.split_whitespace().next()?.parse().ok()?))
+        })
+        .collect();
+    let value = |key: &str| values.get(key).copied().unwrap_or(0);
+    MemInfo {
+        buffers: value("Buffers"),
+        cached: value("Cached"),
+        slab: value("Slab"),
+        slab_reclaimable: value("SReclaimable"),
+    }
+}
+
+pub fn read_meminfo() -> io::Result<MemInfo> {
+    fs::read_to_string("/proc/meminfo").map(|content| parse_meminfo(&content))
+}
+
+/// /proc/vmstat中启动以来的累计次数
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct VmStat {
+    ///换入、换出的页数
+    pub pswpin: u64,
+    pub pswpout: u64,
+    ///主缺页(需要读磁盘)次数
+    pub pgmajfault: u64,
+}
+
+pub fn parse_vmstat(content: &str) -> VmStat {
+    let mut vmstat = VmStat::default();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let (key, value) = match (fields.next(), fields.next().and_then(|v| v.parse().ok())) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        match key {
+            "pswpin" => vmstat.pswpin = value,
+            "pswpout" => vmstat.pswpout = value,
+            "pgmajfault" => vmstat.pgmajfault = value,
+            _ => {}
+        }
+    }
+    vmstat
+}
+
+pub fn read_vmstat() -> io::Result<VmStat> {
+    fs::read_to_string("/proc/vmstat").map(|content| parse_vmstat(&content))
+}
+
+/// 每秒换入换出页数及主缺页次数，由相邻两次采集的差值计算
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Paging {
+    pub swap_in_per_sec: f32,
+    pub swap_out_per_sec: f32,
+    pub major_faults_per_sec: f32,
+}
+
+impl Paging {
+    /// 间隔为0或计数器重置时返回None
+    pub fn since(now: &VmStat, before: &VmStat, elapsed: Duration) -> Option<Paging> {
+        let secs = elapsed.as_secs_f32();
+        if secs <= 0.0 {
+            return None;
+        }
+        let per_sec = |now: u64, before: u64| now.checked_sub(before).map(|d| d as f32 / secs);
+        Some(Paging {
+            swap_in_per_sec: per_sec(now.pswpin, before.pswpin)?,
+            swap_out_per_sec: per_sec(now.pswpout, before.pswpout)?,
+            major_faults_per_sec: per_sec(now.pgmajfault, before.pgmajfault)?,
+        })
+    }
+}
+
+/// 最近10、60、300秒内因内存不足而等待的时间占比，百分比
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct PressureAvg {
+    pub avg10: f32,
+    pub avg60: f32,
+    pub avg300: f32,
+}
+
+/// /proc/pressure/memory(PSI，内核4.20及以上)
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Pressure {
+    ///至少一个任务在等待内存
+    pub some: PressureAvg,
+    ///全部任务都在等待内存
+    pub full: Option<PressureAvg>,
+}
+
+/// 解析`some avg10=0.00 avg60=0.00 avg300=0.00 total=0`形式的行
+pub fn parse_pressure(content: &str) -> Option<Pressure> {
+    let mut some = None;
+    let mut full = None;
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next();
+        let values: HashMap<&str, f32> = fields.filter_map(|f| f.split_once('=')).filter_map(|(k, v)| Some((k, v.parse().ok()?))).collect();
+        let avg = match (values.get("avg10"), values.get("avg60"), values.get("avg300")) {
+            (Some(avg10), Some(avg60), Some(avg300)) => PressureAvg {
+                avg10: *avg10,
+                avg60: *avg60,
+                avg300: *avg300,
+            },
+            _ => continue,
+        };
+        match kind {
+            Some("some") => some = Some(avg),
+            Some("full") => full = Some(avg),
+            _ => {}
+        }
+    }
+    Some(Pressure { some: some?, full })
+}
+
+pub fn read_pressure() -> io::Result<Pressure> {
+    let content = fs::read_to_string("/proc/pressure/memory")?;
+    parse_pressure(&content).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("无法解析/proc/pressure/memory: {}", content.trim())))
+}
+
+#[test]
+fn test_parse_meminfo() {
+    let content = "MemTotal:       16314992 kB\nMemFree:         1021360 kB\nBuffers:          413420 kB\nCached:          6846180 kB\nSwapCached:        12544 kB\nSlab:             905432 kB\nSReclaimable:     702112 kB\n";
+    assert_eq!(
+        parse_meminfo(content),
+        MemInfo {
+            buffers: 413420,
+            cached: 6846180,
+            slab: 905432,
+            slab_reclaimable: 702112,
+        }
+    );
+    assert_eq!(parse_meminfo("Buffers: 1 kB\n").slab, 0);
+}
+
+#[test]
+fn test_paging() {
+    let before = parse_vmstat("nr_free_pages 254120\npswpin 100\npswpout 400\npgfault 9999\npgmajfault 2000\n");
+    let now = parse_vmstat("pswpin 150\npswpout 400\npgmajfault 2030\n");
+    assert_eq!((before.pswpin, before.pswpout, before.pgmajfault), (100, 400, 2000));
+
+    let paging = Paging::since(&now, &before, Duration::from_secs(10)).unwrap();
+    assert_eq!(paging.swap_in_per_sec, 5.0);
+    assert_eq!(paging.swap_out_per_sec, 0.0);
+    assert_eq!(paging.major_faults_per_sec, 3.0);
+
+    assert_eq!(Paging::since(&before, &now, Duration::from_secs(10)), None);
+    assert_eq!(Paging::since(&now, &before, Duration::ZERO), None);
+}
+
+#[test]
+fn test_parse_pressure() {
+    let pressure = parse_pressure("some avg10=1.50 avg60=0.80 avg300=0.20 total=123456\nfull avg10=0.50 avg60=0.10 avg300=0.00 total=2345\n").unwrap();
+    assert_eq!((pressure.some.avg10, pressure.some.avg300), (1.5, 0.2));
+    assert_eq!(pressure.full.unwrap().avg60, 0.1);
+
+    //旧内核只有some
+    assert_eq!(parse_pressure("some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n").unwrap().full, None);
+    assert_eq!(parse_pressure(""), None);
+}
@@ -1,7 +1,10 @@
 use log::info;
-use mix_agent_common::mix_config::{init_logger, MixConfig};
-use mix_agent_common::{init_log, mix_config, post_log, GlobalConfig, LogLevel, Monitor};
+use mix_agent_common::mix_collector::{CollectError, Collector};
+use mix_agent_common::mix_config::{init_logger, AgentConfig, MixConfig};
+use mix_agent_common::mix_host::Host;
+use mix_agent_common::Monitor;
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
 use sysinfo::{ProcessExt, System, SystemExt};
 
 const AGENT_NAME: &str = "mix_agent_process";
@@ -24,6 +27,8 @@ pub struct ProcessAgentConfig {
     #[serde(default = "default_cron")]
     cron: String,
     #[serde(default)]
+    timezone: String,
+    #[serde(default)]
     target: Vec<Target>,
 }
 
@@ -54,6 +59,7 @@ impl Default for ProcessAgentConfig {
             name: "".to_string(),
             enabled: false,
             cron: default_cron(),
+            timezone: String::new(),
             target: vec![],
         }
     }
@@ -65,57 +71,97 @@ impl MixConfig for ProcessAgentConfig {
     }
 }
 
+impl AgentConfig for ProcessAgentConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+}
+
 impl Monitor for Process {
     fn collect(&self) {
-        let sys = System::new_all();
-        let global_config = mix_config::load::<GlobalConfig>("global");
-        let agent_config = mix_config::load::<ProcessAgentConfig>(AGENT_NAME);
-        info!("{:?}", global_config);
-        info!("{:?}", agent_config);
-        let mut tags = vec![];
-        tags.push("agent-desc|进程监控".to_owned());
-        Self::begin(&agent_config.cron, || {
-            let mut result: Vec<Process> = vec![];
-
-            if agent_config.target.len() == 0 {
-                let log = init_log("agent", "70001:未配置要监控的目标进程", LogLevel::Warn, Box::new(tags.clone()), "", AGENT_NAME);
-                post_log(&log);
-            } else {
-                let process = sys.processes();
-                for target in agent_config.target.iter() {
-                    let current = &target;
-                    let mut item = Process::default();
-                    item.name = current.name.clone();
-                    for (pid, process) in process.iter() {
-                        #[cfg(debug_assertions)]
-                        info!("{} {:?} {}", pid, process.name(), process.cmd().len());
-
-                        let exe_path = process.exe().as_os_str().to_str().unwrap();
-                        if process.name() == target.name || exe_path.contains(&current.name) {
-                            item.pid = *pid as i32;
-                            item.start_time = process.start_time() as i64 * 1000;
-                            item.is_exist = true;
-                            item.status = get_process_status(process.status().as_str());
-
-                            #[cfg(debug_assertions)]
-                            info!("{} {} {} {}", pid, process.status(), process.start_time(), item.start_time);
-                            #[cfg(debug_assertions)]
-                            info!("{:?}", process);
-
-                            break;
-                        } else {
-                            item.pid = -1;
-                            item.status = "Unknown".to_string();
-                        }
-                    }
-
-                    result.push(item);
-                }
+        let mut host = Host::new();
+        Process::register(&mut host, Arc::new(Mutex::new(System::new_all())));
+        host.run();
+    }
+}
+
+impl Process {
+    /// 注册到探针宿主，`sys`可由多个探针共用
+    pub fn register(host: &mut Host, sys: Arc<Mutex<System>>) {
+        host.add_collector(ProcessCollector::new(sys));
+    }
+}
+
+pub struct ProcessCollector {
+    sys: Arc<Mutex<System>>,
+}
 
-                let log = init_log("process", "", LogLevel::Info, Box::new(tags.clone()), &result, AGENT_NAME);
-                post_log(&log);
+impl ProcessCollector {
+    pub fn new(sys: Arc<Mutex<System>>) -> ProcessCollector {
+        ProcessCollector { sys }
+    }
+}
+
+impl Collector for ProcessCollector {
+    type Config = ProcessAgentConfig;
+    type Output = Vec<Process>;
+
+    fn agent_name(&self) -> &str {
+        AGENT_NAME
+    }
+
+    fn category(&self) -> &str {
+        "process"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|进程监控".to_owned()]
+    }
+
+    fn sample(&mut self, agent_config: &ProcessAgentConfig) -> Result<Vec<Process>, CollectError> {
+        if agent_config.target.len() == 0 {
+            return Err(CollectError::warn("70001:未配置要监控的目标进程"));
+        }
+
+        let mut result: Vec<Process> = vec![];
+        let mut sys = self.sys.lock().unwrap();
+        sys.refresh_processes();
+        let process = sys.processes();
+        for target in agent_config.target.iter() {
+            let current = &target;
+            let mut item = Process::default();
+            item.name = current.name.clone();
+            for (pid, process) in process.iter() {
+                #[cfg(debug_assertions)]
+                info!("{} {:?} {}", pid, process.name(), process.cmd().len());
+
+                let exe_path = process.exe().as_os_str().to_str().unwrap();
+                if process.name() == target.name || exe_path.contains(&current.name) {
+                    item.pid = *pid as i32;
+                    item.start_time = process.start_time() as i64 * 1000;
+                    item.is_exist = true;
+                    item.status = get_process_status(process.status().as_str());
+
+                    #[cfg(debug_assertions)]
+                    info!("{} {} {} {}", pid, process.status(), process.start_time(), item.start_time);
+                    #[cfg(debug_assertions)]
+                    info!("{:?}", process);
+
+                    break;
+                } else {
+                    item.pid = -1;
+                    item.status = "Unknown".to_string();
+                }
             }
-        });
+
+            result.push(item);
+        }
+
+        Ok(result)
     }
 }
 
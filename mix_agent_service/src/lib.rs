@@ -1,5 +1,8 @@
 use log::{info, warn};
-use mix_agent_common::mix_config::{init_logger, MixConfig};
+#[cfg(target_os = "windows")]
+use mix_agent_common::mix_collector::{CollectError, Collector};
+use mix_agent_common::mix_config::{init_logger, AgentConfig, MixConfig};
+use mix_agent_common::mix_host::Host;
 use mix_agent_common::{Monitor};
 use serde::{Deserialize, Serialize};
 
@@ -21,6 +24,8 @@ pub struct ServiceAgentConfig {
     #[serde(default = "default_cron")]
     cron: String,
     #[serde(default)]
+    timezone: String,
+    #[serde(default)]
     services: Vec<Target>,
 }
 
@@ -38,6 +43,7 @@ impl Default for ServiceAgentConfig {
             name: "".to_string(),
             enabled: false,
             cron: default_cron(),
+            timezone: String::new(),
             services: vec![],
         }
     }
@@ -49,6 +55,16 @@ impl MixConfig for ServiceAgentConfig {
     }
 }
 
+impl AgentConfig for ServiceAgentConfig {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn timezone(&self) -> &str {
+        &self.timezone
+    }
+}
+
 ///默认每10分钟执行一次
 fn default_cron() -> String {
     "0 0/10 * * * ?".to_string()
@@ -84,65 +100,95 @@ impl Monitor for Service {
 #[cfg(target_os = "windows")]
 impl Monitor for Service {
     fn collect(&self) {
-        let global_config = mix_config::load::<GlobalConfig>("global");
-        let agent_config = mix_config::load::<ServiceAgentConfig>(AGENT_NAME);
-        info!("{:?}", global_config);
-        info!("{:?}", agent_config);
-
-        let mut tags = vec![];
-        tags.push("agent-desc|windows服务监控".to_owned());
-
-        Self::begin(&agent_config.cron, || {
-            let mut result: Vec<Service> = vec![];
-            if agent_config.services.len() == 0 {
-                let log = init_log("agent", "60001:未配置要监控的windows服务", LogLevel::Warn, Box::new(tags.clone()), "", AGENT_NAME);
-                post_log(&log);
-            } else {
-                for service in agent_config.services.iter() {
-                    let service_name = &service.name;
-                    let mut item = Service::default();
-                    item.pid = -1;
-                    item.name = service_name.to_string();
-                    item.force_restart = service.force_restart;
-
-                    let manager_access = ServiceManagerAccess::CONNECT;
-                    let service_manager = ServiceManager::local_computer(None::<&str>, manager_access).unwrap();
-                    let service_result = service_manager.open_service(service_name, ServiceAccess::QUERY_STATUS);
-
-                    match service_result {
-                        Ok(s) => {
-                            let status = s.query_status().unwrap();
-                            let state = status.current_state;
-
-                            item.is_exist = true;
-                            item.display_name = get_display_name(service_name, &service_manager);
-                            item.status = "Unknown".to_string();
-
-                            if state == ServiceState::Running {
-                                item.pid = status.process_id.unwrap() as i32;
-                                item.status = state.to_string();
-                            }
-
-                            if state == ServiceState::Stopped {
-                                Service::restart_service(service, service_name, &mut item, service_manager)
-                            }
-                        }
-                        Err(e) => {
-                            item.pid = -1;
-                            item.remark = format!("{:?}", e).to_string();
-                            item.status = "Unknown".to_string();
-                            println!("{:?}", e);
-                        }
+        let mut host = Host::new();
+        Service::register(&mut host);
+        host.run();
+    }
+}
+
+#[cfg(not(windows))]
+impl Service {
+    /// 注册到探针宿主，仅支持windows
+    pub fn register(_host: &mut Host) {
+        warn!("The agent is only for windows, this platform({}) is not support!", std::env::consts::OS);
+    }
+}
+#[cfg(target_os = "windows")]
+impl Service {
+    /// 注册到探针宿主
+    pub fn register(host: &mut Host) {
+        host.add_collector(ServiceCollector);
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub struct ServiceCollector;
+
+#[cfg(target_os = "windows")]
+impl Collector for ServiceCollector {
+    type Config = ServiceAgentConfig;
+    type Output = Vec<Service>;
+
+    fn agent_name(&self) -> &str {
+        AGENT_NAME
+    }
+
+    fn category(&self) -> &str {
+        "service-windows"
+    }
+
+    fn tags(&self) -> Vec<String> {
+        vec!["agent-desc|windows服务监控".to_owned()]
+    }
+
+    fn sample(&mut self, agent_config: &ServiceAgentConfig) -> Result<Vec<Service>, CollectError> {
+        if agent_config.services.len() == 0 {
+            return Err(CollectError::warn("60001:未配置要监控的windows服务"));
+        }
+
+        let mut result: Vec<Service> = vec![];
+        for service in agent_config.services.iter() {
+            let service_name = &service.name;
+            let mut item = Service::default();
+            item.pid = -1;
+            item.name = service_name.to_string();
+            item.force_restart = service.force_restart;
+
+            let manager_access = ServiceManagerAccess::CONNECT;
+            let service_manager = ServiceManager::local_computer(None::<&str>, manager_access).unwrap();
+            let service_result = service_manager.open_service(service_name, ServiceAccess::QUERY_STATUS);
+
+            match service_result {
+                Ok(s) => {
+                    let status = s.query_status().unwrap();
+                    let state = status.current_state;
+
+                    item.is_exist = true;
+                    item.display_name = get_display_name(service_name, &service_manager);
+                    item.status = "Unknown".to_string();
+
+                    if state == ServiceState::Running {
+                        item.pid = status.process_id.unwrap() as i32;
+                        item.status = state.to_string();
                     }
 
-                    result.push(item);
+                    if state == ServiceState::Stopped {
+                        Service::restart_service(service, service_name, &mut item, service_manager)
+                    }
+                }
+                Err(e) => {
+                    item.pid = -1;
+                    item.remark = format!("{:?}", e).to_string();
+                    item.status = "Unknown".to_string();
+                    println!("{:?}", e);
                 }
-
-                println!("{:?}", result);
-                let log = init_log("service-windows", "", LogLevel::Info, Box::new(tags.clone()), &result, AGENT_NAME);
-                post_log(&log);
             }
-        });
+
+            result.push(item);
+        }
+
+        println!("{:?}", result);
+        Ok(result)
     }
 }
 
@@ -0,0 +1,75 @@
+//! 加密配置值
+//!
+//! 输出 `ENC(...)`，可直接替换配置文件中的明文，如 `mix-endpoint-key`、mix_agent_api.yml中的 `auth.keys`。
+//! 密钥文件不存在时生成，密文只能在本机解密，须在探针所在主机上执行。
+
+use mix_agent_common::mix_override::{key_file, OPTION_NAMES};
+use mix_agent_common::mix_secret::{encrypt, ensure_key_file, host_id};
+use std::io::{self, BufRead};
+
+/// 待加密的值，未在命令行指定时从标准输入读取一行，避免明文留在命令历史中；
+/// 以 `-` 开头的值须写在 `--` 之后，未知选项报错而不是当作待加密的值
+fn plain_text(args: &[String]) -> Result<String, String> {
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let name = arg.split_once('=').map_or(arg.as_str(), |(n, _)| n);
+        if arg == "--" {
+            if let Some(value) = rest.next() {
+                return Ok(value.clone());
+            }
+        } else if OPTION_NAMES.contains(&name) {
+            if name == arg {
+                rest.next();
+            }
+        } else if arg.starts_with('-') {
+            return Err(format!("未知的选项: {}", arg));
+        } else {
+            return Ok(arg.clone());
+        }
+    }
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).map_err(|e| format!("读取待加密的值失败: {}", e))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let plain = match plain_text(args) {
+        Ok(p) if !p.is_empty() => p,
+        Ok(_) => {
+            eprintln!("待加密的值不能为空");
+            return 2;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let path = key_file();
+    match ensure_key_file(&path) {
+        Ok(seed) => {
+            eprintln!("密钥文件: {}", path.display());
+            println!("{}", encrypt(&seed, &host_id(), &plain));
+            0
+        }
+        Err(e) => {
+            eprintln!("无法读取或生成密钥文件`{}`: {}", path.display(), e);
+            1
+        }
+    }
+}
+
+#[test]
+fn test_plain_text() {
+    let args: Vec<String> = vec!["--key-file", "/etc/mix/mix.key", "--config-dir=/etc/mix", "s3cret"].into_iter().map(String::from).collect();
+    assert_eq!(plain_text(&args).unwrap(), "s3cret");
+}
+
+#[test]
+fn test_plain_text_options() {
+    let args = |a: &[&str]| -> Vec<String> { a.iter().map(|s| s.to_string()).collect() };
+    assert_eq!(plain_text(&args(&["--log-dir", "/x", "s3cret"])).unwrap(), "s3cret");
+    assert_eq!(plain_text(&args(&["--set", "MIX_CUSTOMER_ID=c", "--", "--s3cret"])).unwrap(), "--s3cret");
+    assert!(plain_text(&args(&["--verbose", "s3cret"])).unwrap_err().contains("--verbose"));
+    assert!(plain_text(&args(&["-v", "s3cret"])).is_err());
+}
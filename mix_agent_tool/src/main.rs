@@ -0,0 +1,29 @@
+use std::env;
+use std::process;
+
+mod encrypt;
+mod validate_config;
+
+const USAGE: &str = "用法:
+  mix_agent_tool validate-config [--config-dir <目录>] [--set <变量名>=<值>] [--format human|json]
+      校验global.yml及全部mix_agent_*.yml(含环境变量及命令行覆盖)，存在错误时返回非0退出码
+  mix_agent_tool encrypt [--key-file <文件>] [--] [<值>]
+      加密配置值，输出ENC(...)；未指定值时从标准输入读取，以-开头的值须写在--之后，密钥文件默认为配置目录/mix.key";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.first().map(|s| s.as_str()) {
+        Some("validate-config") => validate_config::run(&args[1..]),
+        Some("encrypt") => encrypt::run(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    };
+    process::exit(code);
+}
+
+/// 取 `--name value` 形式的参数
+pub fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(|s| s.as_str())
+}
@@ -0,0 +1,159 @@
+//! 离线校验配置目录
+//!
+//! 使用探针相同的配置类型解析global.yml及全部mix_agent_*.yml，按 `Monitor::begin` 相同的方式解析 `cron`，
+//! 并执行各探针配置的校验(如mix_agent_api的关键字表达式)，输出文本或json格式的报告。
+
+use crate::option;
+use mix_agent_api::ApiAgentConfig;
+use mix_agent_common::mix_config::ConfigError;
+use mix_agent_common::mix_override::config_dir;
+use mix_agent_common::mix_reload::{reload, reload_global};
+use mix_agent_cpu::CpuAgentConfig;
+use mix_agent_directory::AppScanConfig;
+use mix_agent_disk::DiskAgentConfig;
+use mix_agent_machine::MachineAgentConfig;
+use mix_agent_memory::MemoryAgentConfig;
+use mix_agent_process::ProcessAgentConfig;
+use mix_agent_service::ServiceAgentConfig;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Debug)]
+pub struct FileReport {
+    pub file: String,
+    pub errors: Vec<ConfigError>,
+    ///未校验的原因，如未知的配置文件
+    pub skipped: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub config_dir: String,
+    pub files: Vec<FileReport>,
+    pub error_count: usize,
+}
+
+/// 按文件名选择配置类型校验，未知的文件返回None
+fn check_file(name: &str, path: &Path) -> Option<Result<(), ConfigError>> {
+    let result = match name {
+        "global" => reload_global(path).map(|_| ()),
+        "mix_agent_api" => reload::<ApiAgentConfig>(path).map(|_| ()),
+        "mix_agent_cpu" => reload::<CpuAgentConfig>(path).map(|_| ()),
+        "mix_agent_directory" => reload::<AppScanConfig>(path).map(|_| ()),
+        "mix_agent_disk" => reload::<DiskAgentConfig>(path).map(|_| ()),
+        "mix_agent_machine" => reload::<MachineAgentConfig>(path).map(|_| ()),
+        "mix_agent_memory" => reload::<MemoryAgentConfig>(path).map(|_| ()),
+        "mix_agent_process" => reload::<ProcessAgentConfig>(path).map(|_| ()),
+        "mix_agent_service" => reload::<ServiceAgentConfig>(path).map(|_| ()),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// 校验配置目录，global.yml必须存在
+pub fn validate_dir(config_dir: &Path) -> Report {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(config_dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.extension().map(|e| e == "yml").unwrap_or(false)).collect(),
+        Err(_) => vec![],
+    };
+    let global = config_dir.join("global.yml");
+    if !paths.contains(&global) {
+        paths.push(global);
+    }
+    paths.sort();
+
+    let mut files = vec![];
+    for path in paths.iter() {
+        let name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let file = path.to_string_lossy().to_string();
+        let report = match check_file(&name, path) {
+            Some(Ok(_)) => FileReport { file, errors: vec![], skipped: None },
+            Some(Err(e)) => FileReport { file, errors: vec![e], skipped: None },
+            None => FileReport {
+                file,
+                errors: vec![],
+                skipped: Some("未知的配置文件".to_string()),
+            },
+        };
+        files.push(report);
+    }
+
+    let error_count = files.iter().map(|f| f.errors.len()).sum();
+    Report {
+        config_dir: config_dir.to_string_lossy().to_string(),
+        files,
+        error_count,
+    }
+}
+
+/// 文本格式的报告
+pub fn render(report: &Report) -> String {
+    let mut lines = vec![format!("配置目录: {}", report.config_dir)];
+    for file in report.files.iter() {
+        let name = Path::new(&file.file).file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        if let Some(reason) = &file.skipped {
+            lines.push(format!("[跳过] {}: {}", name, reason));
+        } else if file.errors.is_empty() {
+            lines.push(format!("[通过] {}", name));
+        } else {
+            lines.push(format!("[错误] {}", name));
+            for e in file.errors.iter() {
+                lines.push(format!("       {} {}", e.code(), e));
+            }
+        }
+    }
+    lines.push(format!("共校验{}个文件，{}个错误", report.files.iter().filter(|f| f.skipped.is_none()).count(), report.error_count));
+    lines.join("\n")
+}
+
+pub fn run(args: &[String]) -> i32 {
+    //`--config-dir` 与探针相同，由 `mix_override` 解析
+    let report = validate_dir(&config_dir());
+    match option(args, "--format").unwrap_or("human") {
+        "human" => println!("{}", render(&report)),
+        "json" => println!("{}", serde_json::to_string_pretty(&report).expect("报告可序列化")),
+        other => {
+            eprintln!("不支持的输出格式: {}", other);
+            return 2;
+        }
+    }
+
+    if report.error_count > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+#[test]
+fn test_validate_dir() {
+    let dir = std::env::temp_dir().join(format!("mix_validate_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("global.yml"), "customer-id: c\nproject-id: p\nmix-endpoint: http://mix.wushiai.com\nmix-endpoint-key: k\n").unwrap();
+    fs::write(dir.join("mix_agent_disk.yml"), "cron: * 0/30 * * * * ?\n").unwrap();
+    fs::write(dir.join("mix_agent_memory.yml"), "cron: \"every 10 seconds\"\n").unwrap();
+    fs::write(dir.join("mix_agent_api.yml"), "cron: \"*/15 * * * * ?\"\ntarget-source:\n  ip: 127.0.0.1\ntargets:\n  - name: health\n    keywords:\n      status: .status|ok\n").unwrap();
+    fs::write(dir.join("mix_agent_process.yml"), "cron: \"0 0/10 * * * ?\"\ntarget:\n  - name: nginx\n").unwrap();
+    fs::write(dir.join("other.yml"), "a: 1\n").unwrap();
+
+    let report = validate_dir(&dir);
+    assert_eq!(report.files.len(), 6);
+    assert_eq!(report.error_count, 3);
+    let errors: Vec<&ConfigError> = report.files.iter().flat_map(|f| f.errors.iter()).collect();
+    assert_eq!(errors[0].code(), "10004");
+    assert_eq!(errors[0].path, "targets[0].keywords.status");
+    assert!(errors[0].reason.contains("表达式配置错"));
+    assert_eq!(errors[1].code(), "10003");
+    assert_eq!(errors[2].path, "cron");
+
+    let text = render(&report);
+    assert!(text.contains("[通过] global.yml"));
+    assert!(text.contains("[跳过] other.yml"));
+    assert!(text.ends_with("共校验5个文件，3个错误"));
+
+    let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+    assert_eq!(json["files"][2]["errors"][0]["kind"], "syntax");
+
+    fs::remove_dir_all(&dir).unwrap();
+}